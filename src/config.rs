@@ -0,0 +1,228 @@
+//! Config Module
+//!
+//! The project config file combining all the command line options, so scheduled
+//! jobs don't have to carry long command lines around. Command line arguments
+//! take precedence over the config values.
+
+use crate::formats::{read_file, ParseError};
+use crate::inputs::STDIN_PATTERN;
+use crate::models::{get_weights_overrides, Weights};
+use crate::rules::parse_rules;
+use crate::rules::schema::CustomRules;
+use crate::scores::duplicates::DuplicatePolicy;
+use crate::scores::export::OutputFormat;
+use crate::scores::normalize::Normalization;
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// The config file names looked for in the working directory, in order.
+pub const CONFIG_FILES: [&str; 4] = ["score.toml", "score.json", "score.yaml", "score.yml"];
+
+/// The project config. Relative paths are resolved against the directory of
+/// the config file.
+#[derive(Deserialize, Default)]
+pub struct ProjectConfig {
+    /// Glob patterns for the input files, a single pattern or a list.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub data: Option<Vec<String>>,
+    /// Glob patterns for input files to skip, a single pattern or a list.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub exclude: Option<Vec<String>>,
+    /// The weight overrides, either inline or a path to an overrides file.
+    pub weights: Option<FileOrInline<Weights>>,
+    /// The custom rules, either inline or a path to a rules file.
+    pub rules: Option<FileOrInline<CustomRules>>,
+    /// The run mode, `map`, `aggregate` or `overwrite`.
+    pub mode: Option<String>,
+    /// The policy for duplicate biomarker IDs in map mode and compare.
+    pub duplicates: Option<DuplicatePolicy>,
+    /// The optional normalisation, percentile ranking and tiering of the scores.
+    pub normalize: Option<Normalization>,
+    #[serde(default)]
+    pub output: OutputConfig,
+}
+
+/// A config value that can be given inline or as a path to a file.
+pub enum FileOrInline<T> {
+    File(String),
+    Inline(T),
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for FileOrInline<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FileOrInlineVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for FileOrInlineVisitor<T> {
+            type Value = FileOrInline<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a file path or an inline table")
+            }
+
+            fn visit_str<E: de::Error>(self, path: &str) -> Result<Self::Value, E> {
+                Ok(FileOrInline::File(path.to_owned()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                T::deserialize(de::value::MapAccessDeserializer::new(map)).map(FileOrInline::Inline)
+            }
+        }
+
+        deserializer.deserialize_any(FileOrInlineVisitor(PhantomData))
+    }
+}
+
+impl<T> FileOrInline<T> {
+    /// Resolves a relative file path against the base directory.
+    pub fn resolve_path(&mut self, base: &Path) {
+        if let FileOrInline::File(path) = self {
+            if Path::new(path.as_str()).is_relative() {
+                *path = base.join(path.as_str()).to_string_lossy().into_owned();
+            }
+        }
+    }
+}
+
+impl FileOrInline<Weights> {
+    /// Resolves a relative file path, and the relative table paths of inline
+    /// weights, against the base directory.
+    pub fn resolve_paths(&mut self, base: &Path) {
+        self.resolve_path(base);
+        if let FileOrInline::Inline(weights) = self {
+            weights.resolve_paths(base);
+        }
+    }
+
+    /// Returns the weight overrides merged with the default weights. Relative
+    /// table paths in an overrides file are resolved against its directory.
+    pub fn weights(&self) -> Result<Weights, ParseError> {
+        match self {
+            FileOrInline::File(path) => get_weights_overrides(Some(path)),
+            FileOrInline::Inline(overrides) => {
+                Weights::with_defaults(Some(overrides)).load_tables()
+            }
+        }
+    }
+}
+
+impl FileOrInline<CustomRules> {
+    /// Returns the rules, reading them from the rules file if given by path.
+    pub fn into_rules(self) -> Result<Option<CustomRules>, ParseError> {
+        match self {
+            FileOrInline::File(path) => parse_rules(Some(&path)),
+            FileOrInline::Inline(rules) => Ok(Some(rules)),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct OutputConfig {
+    /// The score map output file in map mode.
+    pub path: Option<String>,
+    /// The score map output format, `json`, `csv`, `tsv`, `parquet` or `sqlite`.
+    pub format: Option<OutputFormat>,
+    /// The optional rule coverage report file.
+    pub rule_report: Option<String>,
+    /// The optional duplicate biomarker ID report file.
+    pub duplicate_report: Option<String>,
+    /// The optional score distribution statistics file.
+    pub stats: Option<String>,
+    /// The directory the score map file keys are relative to.
+    pub key_root: Option<String>,
+    /// Key the score map by absolute file paths.
+    #[serde(default)]
+    pub absolute_keys: bool,
+}
+
+impl ProjectConfig {
+    /// Reads the config file (JSON, YAML or TOML by file extension).
+    pub fn load(path: &str) -> Result<Self, ParseError> {
+        let mut config: ProjectConfig = read_file(path)?;
+        if let Some(base) = Path::new(path).parent() {
+            config.resolve_paths(base);
+        }
+        Ok(config)
+    }
+
+    /// Looks for one of the `CONFIG_FILES` in the working directory.
+    pub fn discover() -> Result<Option<Self>, ParseError> {
+        match CONFIG_FILES.iter().find(|f| Path::new(f).is_file()) {
+            Some(path) => Ok(Some(ProjectConfig::load(path)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the config weights merged with the default weights.
+    pub fn weights(&self) -> Result<Weights, ParseError> {
+        match &self.weights {
+            Some(weights) => weights.weights(),
+            None => Ok(Weights::with_defaults(None)),
+        }
+    }
+
+    /// Takes the config rules, reading them from the rules file if given by path.
+    pub fn take_rules(&mut self) -> Result<Option<CustomRules>, ParseError> {
+        match self.rules.take() {
+            Some(rules) => rules.into_rules(),
+            None => Ok(None),
+        }
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        let resolve = |path: &mut String| {
+            if path != STDIN_PATTERN && Path::new(path.as_str()).is_relative() {
+                *path = base.join(path.as_str()).to_string_lossy().into_owned();
+            }
+        };
+        for path in self
+            .data
+            .iter_mut()
+            .flatten()
+            .chain(self.exclude.iter_mut().flatten())
+        {
+            resolve(path);
+        }
+        if let Some(weights) = self.weights.as_mut() {
+            weights.resolve_paths(base);
+        }
+        if let Some(rules) = self.rules.as_mut() {
+            rules.resolve_path(base);
+        }
+        if let Some(path) = self.output.path.as_mut() {
+            resolve(path);
+        }
+        if let Some(path) = self.output.rule_report.as_mut() {
+            resolve(path);
+        }
+        if let Some(path) = self.output.duplicate_report.as_mut() {
+            resolve(path);
+        }
+        if let Some(path) = self.output.stats.as_mut() {
+            resolve(path);
+        }
+        if let Some(path) = self.output.key_root.as_mut() {
+            resolve(path);
+        }
+    }
+}
+
+/// Deserializes a single string or a list of strings.
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(value)) => Some(vec![value]),
+        Some(OneOrMany::Many(values)) => Some(values),
+        None => None,
+    })
+}
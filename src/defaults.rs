@@ -9,3 +9,8 @@ pub const OTHER_SOURCE: f64 = 0.1;
 pub const LOINC: i32 = 1;
 pub const GENERIC_CONDITION_PEN: i32 = -4;
 pub const GENERIC_CONDITIONS: [&str; 1] = ["DOID:162"];
+pub const PRECISION: u32 = 2;
+pub const MIN_SCORE: f64 = 0.0;
+pub const RECENCY_WEIGHT: f64 = 1.0;
+pub const RECENCY_HALF_LIFE: f64 = 5.0;
+pub const RECENCY_WINDOW: i32 = 5;
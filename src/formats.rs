@@ -0,0 +1,145 @@
+//! Formats Module
+//!
+//! Reads the user provided overrides and rules files as JSON, YAML or TOML
+//! based on the file extension, defaulting to JSON.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// The supported file formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl FileFormat {
+    /// Picks the format from the file extension, defaulting to JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("yaml") | Some("yml") => FileFormat::Yaml,
+            Some("toml") => FileFormat::Toml,
+            _ => FileFormat::Json,
+        }
+    }
+}
+
+/// An error reading or parsing a file, with the location of the error if known.
+#[derive(Debug)]
+pub struct ParseError {
+    pub path: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(
+                f,
+                "Error parsing {} at line {}, column {}: {}",
+                self.path, line, column, self.message
+            ),
+            (Some(line), None) => write!(
+                f,
+                "Error parsing {} at line {}: {}",
+                self.path, line, self.message
+            ),
+            _ => write!(f, "Error parsing {}: {}", self.path, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Reads and deserializes the file in the format given by its extension.
+pub fn read_file<T: DeserializeOwned>(path: &str) -> Result<T, ParseError> {
+    let contents = read_contents(path)?;
+    parse_str(&contents, FileFormat::from_path(Path::new(path)), path)
+}
+
+/// Reads the file contents, reporting failures as a `ParseError`.
+pub fn read_contents(path: &str) -> Result<String, ParseError> {
+    fs::read_to_string(path).map_err(|e| ParseError {
+        path: path.to_owned(),
+        line: None,
+        column: None,
+        message: format!("could not read file: {}", e),
+    })
+}
+
+/// Serializes the value in the given format.
+pub fn to_string<T: Serialize>(
+    value: &T,
+    format: FileFormat,
+) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(match format {
+        FileFormat::Json => serde_json::to_string_pretty(value)?,
+        FileFormat::Yaml => serde_yaml::to_string(value)?,
+        FileFormat::Toml => toml::to_string(value)?,
+    })
+}
+
+/// Deserializes the contents in the given format, `path` is only used for error reporting.
+pub fn parse_str<T: DeserializeOwned>(
+    contents: &str,
+    format: FileFormat,
+    path: &str,
+) -> Result<T, ParseError> {
+    let error = |line, column, message| ParseError {
+        path: path.to_owned(),
+        line,
+        column,
+        message,
+    };
+    match format {
+        FileFormat::Json => serde_json::from_str(contents).map_err(|e| {
+            let message = strip_location(e.to_string(), e.line(), e.column());
+            error(Some(e.line()), Some(e.column()), message)
+        }),
+        FileFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| match e.location() {
+            Some(l) => {
+                let message = strip_location(e.to_string(), l.line(), l.column());
+                error(Some(l.line()), Some(l.column()), message)
+            }
+            None => error(None, None, e.to_string()),
+        }),
+        FileFormat::Toml => toml::from_str(contents).map_err(|e| {
+            let (line, column) = match e.span() {
+                Some(span) => {
+                    let (line, column) = line_column(contents, span.start);
+                    (Some(line), Some(column))
+                }
+                None => (None, None),
+            };
+            error(line, column, e.message().to_owned())
+        }),
+    }
+}
+
+/// Removes the location suffix the JSON and YAML errors append to their messages.
+fn strip_location(message: String, line: usize, column: usize) -> String {
+    let suffix = format!(" at line {} column {}", line, column);
+    match message.strip_suffix(&suffix) {
+        Some(stripped) => stripped.to_owned(),
+        None => message,
+    }
+}
+
+/// Converts a byte offset into a 1-based line and column.
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    (line, column)
+}
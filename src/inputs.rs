@@ -0,0 +1,161 @@
+//! Inputs Module
+//!
+//! Resolves the input data files from the glob patterns, exclusion patterns
+//! and explicit file lists passed by the user.
+
+use glob::Pattern;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+/// An input file along with its score map key.
+#[derive(Debug, Clone)]
+pub struct InputFile {
+    pub path: PathBuf,
+    pub key: String,
+}
+
+/// What the score map keys are relative to.
+#[derive(Debug, Clone, Default)]
+pub enum KeyRoot {
+    /// Relative to the deepest directory containing all the input files.
+    #[default]
+    CommonAncestor,
+    /// Relative to the directory, files outside of it are keyed by their absolute path.
+    Dir(PathBuf),
+    /// The absolute file paths.
+    Absolute,
+}
+
+/// Input files that ended up with the same score map key.
+#[derive(Debug, Clone)]
+pub struct KeyCollision {
+    pub key: String,
+    pub paths: Vec<PathBuf>,
+}
+
+/// The data pattern that reads the list of input files from stdin.
+pub const STDIN_PATTERN: &str = "-";
+
+/// Resolves the input files matched by the glob patterns (`**` matches any
+/// number of directories), minus any file matched by an exclusion pattern.
+/// A `-` pattern reads a newline separated list of files from stdin. Files
+/// matched more than once are only returned the first time they are seen.
+pub fn resolve_inputs(
+    patterns: &[String],
+    excludes: &[String],
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let excludes = excludes
+        .iter()
+        .map(|e| Pattern::new(e))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+
+    for pattern in patterns {
+        let matched: Vec<PathBuf> = if pattern == STDIN_PATTERN {
+            io::stdin()
+                .lock()
+                .lines()
+                .map(|line| line.map(|l| l.trim().to_owned()))
+                .filter(|line| !matches!(line, Ok(l) if l.is_empty()))
+                .map(|line| line.map(PathBuf::from))
+                .collect::<Result<_, _>>()?
+        } else {
+            glob::glob(pattern)?.collect::<Result<_, _>>()?
+        };
+
+        for path in matched {
+            if is_excluded(&path, &excludes) {
+                continue;
+            }
+            let key = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if seen.insert(key) {
+                files.push(path);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+fn is_excluded(path: &Path, excludes: &[Pattern]) -> bool {
+    let stripped = path.strip_prefix(".").unwrap_or(path);
+    excludes
+        .iter()
+        .any(|e| e.matches_path(path) || e.matches_path(stripped))
+}
+
+/// Assigns every input file its score map key.
+pub fn key_inputs(files: &[PathBuf], root: &KeyRoot) -> io::Result<Vec<InputFile>> {
+    let absolute = files
+        .iter()
+        .map(fs::canonicalize)
+        .collect::<io::Result<Vec<_>>>()?;
+    let root = match root {
+        KeyRoot::CommonAncestor => common_ancestor(&absolute),
+        KeyRoot::Dir(dir) => Some(fs::canonicalize(dir)?),
+        KeyRoot::Absolute => None,
+    };
+
+    Ok(files
+        .iter()
+        .zip(absolute)
+        .map(|(path, absolute)| {
+            let relative = root
+                .as_deref()
+                .and_then(|root| absolute.strip_prefix(root).ok())
+                .unwrap_or(&absolute);
+            InputFile {
+                path: path.clone(),
+                key: path_key(relative),
+            }
+        })
+        .collect())
+}
+
+/// Finds the input files that share a score map key.
+pub fn find_key_collisions(inputs: &[InputFile]) -> Vec<KeyCollision> {
+    let mut paths_by_key: HashMap<&str, Vec<PathBuf>> = HashMap::new();
+    for input in inputs {
+        paths_by_key
+            .entry(&input.key)
+            .or_default()
+            .push(input.path.clone());
+    }
+    let mut collisions: Vec<KeyCollision> = paths_by_key
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(key, paths)| KeyCollision {
+            key: key.to_owned(),
+            paths,
+        })
+        .collect();
+    collisions.sort_by(|a, b| a.key.cmp(&b.key));
+    collisions
+}
+
+/// The deepest directory containing all the files.
+fn common_ancestor(files: &[PathBuf]) -> Option<PathBuf> {
+    let mut ancestor = files.first()?.parent()?.to_path_buf();
+    for file in &files[1..] {
+        while !file.starts_with(&ancestor) {
+            if !ancestor.pop() {
+                return None;
+            }
+        }
+    }
+    Some(ancestor)
+}
+
+/// Joins the relative path components with `/` so keys are the same across platforms.
+fn path_key(path: &Path) -> String {
+    if path.is_absolute() {
+        return path.to_string_lossy().into_owned();
+    }
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -1,25 +1,53 @@
+pub mod config;
 pub mod defaults;
+pub mod formats;
+pub mod inputs;
 pub mod models;
 pub mod scores {
+    pub mod aggregate;
     pub mod calculate;
+    pub mod compare;
+    pub mod diff;
+    pub mod duplicates;
+    pub mod explain;
+    pub mod export;
+    pub mod loinc;
     pub mod map;
+    pub mod normalize;
     pub mod overwrite;
+    pub mod stats;
 }
 pub mod rules;
+pub mod tables;
 
 pub mod prelude {
+    pub use crate::config::ProjectConfig;
     pub use crate::defaults::*;
+    pub use crate::formats::ParseError;
+    pub use crate::inputs::{find_key_collisions, key_inputs, resolve_inputs, KeyRoot};
     pub use crate::models::full_models::Biomarker as FullBiomarker;
     pub use crate::models::minimum_models::Biomarker as MinBiomarker;
     pub use crate::models::minimum_models::{Component, Evidence, Specimen};
     pub use crate::models::{
         get_weights_overrides, BiomarkerScore, ScoreContribution, ScoreInfo, Weights,
     };
-    pub use crate::rules::parse_rules;
+    pub use crate::rules::report::RuleReport;
     pub use crate::rules::schema::CustomRules;
+    pub use crate::rules::testing::run_rule_tests;
+    pub use crate::rules::{parse_rules, write_rules};
+    pub use crate::scores::aggregate::generate_aggregate_score_map;
     pub use crate::scores::calculate::calculate_score;
-    pub use crate::scores::map::generate_score_map;
+    pub use crate::scores::compare::{
+        compare_scenarios, load_scenarios, write_comparison, Comparison, Scenario,
+    };
+    pub use crate::scores::diff::{diff_scores, load_scores, ScoreDiff};
+    pub use crate::scores::duplicates::{DuplicatePolicy, DuplicateReport};
+    pub use crate::scores::explain::{explain_biomarker, explain_score, ScoreTrace};
+    pub use crate::scores::export::{write_score_map, OutputFormat};
+    pub use crate::scores::map::{generate_score_map, ScoreMap};
+    pub use crate::scores::normalize::{normalize_score_map, Normalization};
     pub use crate::scores::overwrite::overwrite_source_files;
+    pub use crate::scores::stats::{score_stats, ScoreStats};
 }
 
 pub const CHECKPOINT: usize = 1500;
@@ -5,8 +5,17 @@
 //! of the program based on the user's input.
 
 use biomarker_score_calculator::prelude::*;
-use clap::{Arg, Command};
+use biomarker_score_calculator::scores::normalize::{parse_tiers, NormalizationMethod};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::path::PathBuf;
 use std::process;
+use std::time::Instant;
+
+/// The default score map output file name, without the format extension.
+const DEFAULT_OUTPUT: &str = "biomarker_scores";
+/// The default output file of the compare command.
+const DEFAULT_COMPARISON_OUTPUT: &str = "scenario_comparison.json";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -19,22 +28,34 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .short('d')
                 .long("data")
                 .value_name("PATTERN")
-                .help("Glob pattern for input files (e.g. `./data/*.json`)")
-                .default_value("./data/*.json"),
+                .help("Glob pattern for input files (e.g. `./data/**/*.json`), can be repeated, `-` reads a file list from stdin")
+                .default_value("./data/*.json")
+                .action(ArgAction::Append)
+                .global(true),
+        )
+        .arg(
+            Arg::new("exclude")
+                .short('x')
+                .long("exclude")
+                .value_name("PATTERN")
+                .help("Glob pattern for input files to skip (e.g. `*_schema.json`), can be repeated")
+                .action(ArgAction::Append)
+                .global(true),
         )
         .arg(
             Arg::new("overrides")
                 .short('o')
                 .long("overrides")
                 .value_name("FILE")
-                .help("Optional JSON file for overriding scoring weights and other scoring conditions"),
+                .help("Optional JSON, YAML or TOML file for overriding scoring weights and other scoring conditions")
+                .global(true),
         )
         .arg(
             Arg::new("mode")
                 .short('m')
                 .long("mode")
                 .value_name("MODE")
-                .help("Run mode: 'map' to generate score map, 'overwrite' to update source files")
+                .help("Run mode: 'map' to generate score map, 'aggregate' to score biomarkers across files, 'overwrite' to update source files")
                 .default_value("map"),
         )
         .arg(
@@ -42,40 +63,505 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .short('r')
                 .long("rules")
                 .value_name("RULES")
-                .help("Optional rules file for applying custom scoring logic"))
+                .help("Optional JSON, YAML or TOML rules file for applying custom scoring logic")
+                .global(true),
+        )
+        .arg(
+            Arg::new("rule_report")
+                .long("rule-report")
+                .value_name("FILE")
+                .help("Optional JSON file to write a per-rule coverage and impact report to (requires --rules)"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .value_name("FILE")
+                .help("Output file for the score map in map mode [default: biomarker_scores.<FORMAT>]"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Score map output format: 'json', 'csv'/'tsv'/'parquet' for one row per biomarker, or 'sqlite' to upsert into a database [default: json]")
+                .value_parser(["json", "csv", "tsv", "parquet", "sqlite"]),
+        )
+        .arg(
+            Arg::new("normalize")
+                .long("normalize")
+                .value_name("METHOD")
+                .help("Add a normalised 0-1 score and the percentile rank within the run to every score: 'min-max' or 'logistic'")
+                .value_parser(["min-max", "logistic"]),
+        )
+        .arg(
+            Arg::new("tiers")
+                .long("tiers")
+                .value_name("TIERS")
+                .help("Tier labels by minimum normalised score, e.g. `high:0.7,medium:0.3,low:0` (implies --normalize min-max if not set)"),
+        )
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .value_name("FILE")
+                .help("Optional JSON file to write score distribution statistics to, overall and per source file"),
+        )
+        .arg(
+            Arg::new("key_root")
+                .long("key-root")
+                .value_name("DIR")
+                .help("Directory the score map file keys are relative to [default: the deepest directory containing all input files]"),
+        )
+        .arg(
+            Arg::new("absolute_keys")
+                .long("absolute-keys")
+                .help("Key the score map by absolute file paths")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("key_root"),
+        )
+        .arg(
+            Arg::new("duplicates")
+                .long("duplicates")
+                .value_name("POLICY")
+                .help("Policy for biomarkers sharing an ID within a file in map mode and compare: 'error' (also fails on IDs shared across files), 'keep-first', 'keep-last' or 'merge' [default: keep-last]")
+                .value_parser(["error", "keep-first", "keep-last", "merge"]),
+        )
+        .arg(
+            Arg::new("duplicate_report")
+                .long("duplicate-report")
+                .value_name("FILE")
+                .help("Optional JSON file to write the duplicate biomarker IDs found in map mode to"),
+        )
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .help("Optional project config file, otherwise `score.toml`/`score.json`/`score.yaml` is used if found in the working directory")
+                .global(true),
+        )
+        .subcommand(
+            Command::new("explain")
+                .about("Prints the step-by-step score computation for a single biomarker")
+                .arg(
+                    Arg::new("biomarker_id")
+                        .value_name("BIOMARKER_ID")
+                        .help("The biomarker ID to explain")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .value_name("FILE")
+                        .help("Only look for the biomarker in this file instead of the data patterns"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the trace as JSON")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("convert-rules")
+                .about("Converts a rules file between the JSON, YAML, TOML and rules DSL formats")
+                .arg(
+                    Arg::new("input")
+                        .value_name("INPUT")
+                        .help("The rules file to convert")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .value_name("OUTPUT")
+                        .help("The converted rules file, the format is picked from the extension (`.rules` for the DSL)")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compares the scores of two runs, from score map files or scored source files")
+                .arg(
+                    Arg::new("old")
+                        .value_name("OLD")
+                        .help("The old score map file, or a glob pattern for source files scored in overwrite mode")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("new")
+                        .value_name("NEW")
+                        .help("The new score map file, or a glob pattern for source files scored in overwrite mode")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("threshold")
+                        .long("threshold")
+                        .value_name("DELTA")
+                        .help("Only report score changes larger than this")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("0"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the diff as JSON")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("compare")
+                .about("Scores the input files with several named weight/rule scenarios and compares the rankings")
+                .arg(
+                    Arg::new("scenarios")
+                        .value_name("SCENARIOS")
+                        .help("JSON, YAML or TOML file listing the scenarios, each with a name and optional weights and rules")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Output file for the side-by-side scores and ranks, a CSV/TSV table for `.csv`/`.tsv` files [default: scenario_comparison.json]"),
+                ),
+        )
+        .subcommand(
+            Command::new("test-rules")
+                .about("Runs the test fixtures embedded in the rules file passed with --rules"),
+        )
         .get_matches();
 
+    // Load the project config, command-line arguments take precedence over its values
+    let mut config = match args.get_one::<String>("config") {
+        Some(path) => Some(exit_on_error(ProjectConfig::load(path))),
+        None => exit_on_error(ProjectConfig::discover()),
+    }
+    .unwrap_or_default();
+
     // Extract command-line arguments
-    let glob_pattern = args.get_one::<String>("data").unwrap();
-    let overrides_file_path = args.get_one::<String>("overrides");
-    let weights = get_weights_overrides(overrides_file_path);
-    let rules_file_path = args.get_one::<String>("rules");
-    let custom_rules = parse_rules(rules_file_path);
-    let mode = args.get_one::<String>("mode").unwrap();
-
-    let start_time = std::time::Instant::now();
+    let data_patterns = cli_values(&args, "data")
+        .or(config.data.clone())
+        .unwrap_or_else(|| args.get_many::<String>("data").unwrap().cloned().collect());
+    let exclude_patterns = cli_values(&args, "exclude")
+        .or(config.exclude.clone())
+        .unwrap_or_default();
+    let weights = match args.get_one::<String>("overrides") {
+        Some(path) => exit_on_error(get_weights_overrides(Some(path))),
+        None => exit_on_error(config.weights()),
+    };
+    let custom_rules = match args.get_one::<String>("rules") {
+        Some(path) => exit_on_error(parse_rules(Some(path))),
+        None => exit_on_error(config.take_rules()),
+    };
+    let mut normalization = config.normalize.take();
+    if let Some(method) = args.get_one::<String>("normalize") {
+        normalization
+            .get_or_insert_with(Normalization::default)
+            .method = NormalizationMethod::from_name(method).unwrap();
+    }
+    if let Some(tiers) = args.get_one::<String>("tiers") {
+        let tiers = parse_tiers(tiers).unwrap_or_else(|e| {
+            println!("{}", e);
+            process::exit(1);
+        });
+        normalization
+            .get_or_insert_with(Normalization::default)
+            .tiers = tiers;
+    }
+    let mode = &cli_value(&args, "mode")
+        .or(config.mode.clone())
+        .unwrap_or_else(|| args.get_one::<String>("mode").unwrap().clone());
+    let output_format = args
+        .get_one::<String>("format")
+        .and_then(|format| OutputFormat::from_name(format))
+        .or(config.output.format)
+        .unwrap_or_default();
+    let output_file = &args
+        .get_one::<String>("output")
+        .cloned()
+        .or(config.output.path.clone())
+        .unwrap_or_else(|| format!("{}.{}", DEFAULT_OUTPUT, output_format.extension()));
+    let key_root = key_root(&args, &config);
+    let duplicate_policy = args
+        .get_one::<String>("duplicates")
+        .and_then(|policy| DuplicatePolicy::from_name(policy))
+        .or(config.duplicates)
+        .unwrap_or_default();
+    if let Some(feature) = output_format.missing_feature() {
+        println!(
+            "The {} output format requires building with the `{}` feature",
+            output_format.extension(),
+            feature
+        );
+        process::exit(1);
+    }
+
+    if let Some(("explain", explain_args)) = args.subcommand() {
+        let biomarker_id = explain_args.get_one::<String>("biomarker_id").unwrap();
+        let files = match explain_args.get_one::<String>("file") {
+            Some(file) => vec![PathBuf::from(file)],
+            None => resolve_inputs(&data_patterns, &exclude_patterns)?,
+        };
+        let traces =
+            explain_biomarker(&files, biomarker_id, &weights, custom_rules.as_ref()).await?;
+        if traces.is_empty() {
+            println!("Biomarker {} not found in the input files", biomarker_id);
+            process::exit(1);
+        }
+        if explain_args.get_flag("json") {
+            println!("{}", serde_json::to_string_pretty(&traces)?);
+        } else {
+            for trace in traces {
+                println!("{}\n", trace);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(("convert-rules", convert_args)) = args.subcommand() {
+        let input = convert_args.get_one::<String>("input");
+        let output = convert_args.get_one::<String>("output").unwrap();
+        let rules = exit_on_error(parse_rules(input)).unwrap();
+        if !rules.tests.is_empty() && output.ends_with(".rules") {
+            println!("Warning: rule tests can't be represented in the rules DSL and were dropped.");
+        }
+        write_rules(&rules, output)?;
+        println!("Rules converted and saved to {}", output);
+        return Ok(());
+    }
+
+    if let Some(("diff", diff_args)) = args.subcommand() {
+        let old = load_scores(diff_args.get_one::<String>("old").unwrap()).await?;
+        let new = load_scores(diff_args.get_one::<String>("new").unwrap()).await?;
+        let threshold = *diff_args.get_one::<f64>("threshold").unwrap();
+        let diff = diff_scores(&old, &new, threshold);
+        if diff_args.get_flag("json") {
+            println!("{}", serde_json::to_string_pretty(&diff)?);
+        } else {
+            print!("{}", diff);
+        }
+        return Ok(());
+    }
+
+    if let Some(("compare", compare_args)) = args.subcommand() {
+        let scenarios = load_scenarios(compare_args.get_one::<String>("scenarios").unwrap())?;
+        let files = resolve_inputs(&data_patterns, &exclude_patterns)?;
+        let inputs = key_inputs(&files, &key_root)?;
+        let comparison = compare_scenarios(&inputs, &scenarios, duplicate_policy).await?;
+        let output = compare_args
+            .get_one::<String>("output")
+            .map_or(DEFAULT_COMPARISON_OUTPUT, |output| output.as_str());
+        write_comparison(&comparison, output).await?;
+        println!("{}", comparison);
+        println!("Scenario comparison saved to {}", output);
+        return Ok(());
+    }
+
+    if let Some(("test-rules", _)) = args.subcommand() {
+        let Some(rules) = custom_rules.as_ref() else {
+            println!("The test-rules command requires a rules file.");
+            process::exit(1);
+        };
+        let results = run_rule_tests(rules, &weights);
+        let failed = results.iter().filter(|r| !r.passed()).count();
+        for result in &results {
+            if result.passed() {
+                println!("PASS {}", result.name);
+            } else {
+                println!("FAIL {}", result.name);
+                for failure in &result.failures {
+                    println!("  - {}", failure);
+                }
+            }
+        }
+        println!(
+            "{} tests, {} passed, {} failed",
+            results.len(),
+            results.len() - failed,
+            failed
+        );
+        if failed > 0 {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let rule_report_path = args
+        .get_one::<String>("rule_report")
+        .or(config.output.rule_report.as_ref());
+    let mut rule_report = match (rule_report_path, custom_rules.as_ref()) {
+        (Some(_), Some(rules)) => Some(RuleReport::new(rules)),
+        (Some(_), None) => {
+            println!("A rule report requires a rules file.");
+            process::exit(1);
+        }
+        _ => None,
+    };
+
+    let files = resolve_inputs(&data_patterns, &exclude_patterns)?;
+    if files.is_empty() {
+        println!("No input files matched the data patterns.");
+        process::exit(1);
+    }
+
+    let stats_path = args
+        .get_one::<String>("stats")
+        .or(config.output.stats.as_ref());
+
+    let start_time = Instant::now();
     // Execute the appropriate function based on the run mode argument
-    match mode.as_str() {
-        "map" => {
-            // Generate a score map and save it to a file
-            let score_map = generate_score_map(glob_pattern, &weights, custom_rules).await?;
-            let output_file = "biomarker_scores.json";
-            let serialized_data = serde_json::to_string_pretty(&score_map)?;
-            tokio::fs::write(output_file, serialized_data).await?;
-            println!("Score map generated and saved to {}", output_file);
+    let mut score_map = match mode.as_str() {
+        "map" | "aggregate" => {
+            let inputs = key_inputs(&files, &key_root)?;
+            for collision in find_key_collisions(&inputs) {
+                println!(
+                    "Warning: input files share the score map key `{}` and will be merged:",
+                    collision.key
+                );
+                for path in &collision.paths {
+                    println!("  - {}", path.display());
+                }
+            }
+            if mode == "aggregate" {
+                // Score each biomarker once with the evidence from all the files
+                generate_aggregate_score_map(&inputs, &weights, custom_rules).await?
+            } else {
+                // Generate a score map per file
+                let (score_map, duplicates) =
+                    generate_score_map(&inputs, &weights, custom_rules, duplicate_policy).await?;
+                if !duplicates.is_empty() {
+                    println!(
+                        "Warning: {} biomarker IDs are duplicated within a file and {} across files.",
+                        duplicates.within_files.len(),
+                        duplicates.across_files.len()
+                    );
+                }
+                if let Some(path) = args
+                    .get_one::<String>("duplicate_report")
+                    .or(config.output.duplicate_report.as_ref())
+                {
+                    tokio::fs::write(path, serde_json::to_string_pretty(&duplicates)?).await?;
+                    println!("Duplicate report saved to {}", path);
+                }
+                score_map
+            }
         }
         "overwrite" => {
-            // Overwrite the source files with calculated scores
-            overwrite_source_files(glob_pattern, &weights, custom_rules).await?;
+            // Overwrite the source files with calculated scores, keeping them
+            // in a score map only for the stats
+            let inputs = key_inputs(&files, &key_root)?;
+            let mut score_map = stats_path.map(|_| ScoreMap::new());
+            overwrite_source_files(
+                &inputs,
+                &weights,
+                custom_rules,
+                rule_report.as_mut(),
+                normalization.as_ref(),
+                score_map.as_mut(),
+            )
+            .await?;
+            if let (Some(path), Some(score_map)) = (stats_path, &score_map) {
+                write_stats(score_map, path).await?;
+            }
+            return finish(start_time, rule_report_path, rule_report).await;
         }
         _ => {
             // Handle invalid mode input
-            println!("Invalid mode. Use 'map' or 'overwrite'.");
+            println!("Invalid mode. Use 'map', 'aggregate' or 'overwrite'.");
             process::exit(1);
         }
+    };
+
+    if let Some(normalization) = &normalization {
+        normalize_score_map(&mut score_map, normalization);
+    }
+    if let Some(report) = rule_report.as_mut() {
+        for (biomarker_id, score) in score_map.values().flat_map(|scores| scores.iter()) {
+            report.record_score(biomarker_id, score);
+        }
+    }
+    // Save the score map to a file
+    write_score_map(&score_map, output_file, output_format).await?;
+    println!("Score map generated and saved to {}", output_file);
+    if let Some(path) = stats_path {
+        write_stats(&score_map, path).await?;
+    }
+
+    finish(start_time, rule_report_path, rule_report).await
+}
+
+/// Saves the score distribution statistics and prints the overall summary.
+async fn write_stats(score_map: &ScoreMap, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let stats = score_stats(score_map);
+    tokio::fs::write(path, serde_json::to_string_pretty(&stats)?).await?;
+    println!("Score stats: {}", stats.overall);
+    println!("Score stats saved to {}", path);
+    Ok(())
+}
+
+/// Saves the optional rule report and prints the run time.
+async fn finish(
+    start_time: Instant,
+    rule_report_path: Option<&String>,
+    rule_report: Option<RuleReport>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let (Some(path), Some(report)) = (rule_report_path, rule_report) {
+        tokio::fs::write(path, serde_json::to_string_pretty(&report)?).await?;
+        println!("Rule report saved to {}", path);
+        if !report.unmatched_rules.is_empty() {
+            println!(
+                "{} of {} rules never matched a biomarker:",
+                report.unmatched_rules.len(),
+                report.rules.len()
+            );
+            for rule_name in &report.unmatched_rules {
+                println!("  - {}", rule_name);
+            }
+        }
     }
+
     let elapsed_time = start_time.elapsed();
     println!("Took {} seconds.", elapsed_time.as_secs_f64());
 
     Ok(())
 }
+
+/// The directory the file keys are relative to, from the command line or else the config.
+fn key_root(args: &ArgMatches, config: &ProjectConfig) -> KeyRoot {
+    if args.get_flag("absolute_keys") {
+        KeyRoot::Absolute
+    } else if let Some(dir) = args.get_one::<String>("key_root") {
+        KeyRoot::Dir(PathBuf::from(dir))
+    } else if config.output.absolute_keys {
+        KeyRoot::Absolute
+    } else if let Some(dir) = &config.output.key_root {
+        KeyRoot::Dir(PathBuf::from(dir))
+    } else {
+        KeyRoot::CommonAncestor
+    }
+}
+
+/// Returns the argument values only if they were passed on the command line.
+fn cli_values(args: &ArgMatches, id: &str) -> Option<Vec<String>> {
+    match args.value_source(id) {
+        Some(ValueSource::CommandLine) => args
+            .get_many::<String>(id)
+            .map(|values| values.cloned().collect()),
+        _ => None,
+    }
+}
+
+/// Returns the argument value only if it was passed on the command line, so
+/// config values take precedence over the argument defaults.
+fn cli_value(args: &ArgMatches, id: &str) -> Option<String> {
+    match args.value_source(id) {
+        Some(ValueSource::CommandLine) => args.get_one::<String>(id).cloned(),
+        _ => None,
+    }
+}
+
+/// Prints the parse error and exits.
+fn exit_on_error<T>(result: Result<T, ParseError>) -> T {
+    result.unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(1);
+    })
+}
@@ -5,6 +5,7 @@
 //! so no data is lost when re-dumping the output data.
 
 use super::traits::{BiomarkerData, ComponentData, EvidenceData, SpecimenData};
+use super::{evidence_tags, evidence_year};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -54,6 +55,11 @@ impl ComponentData for Component {
     fn specimen(&self) -> &[Self::Specimen] {
         &self.specimen
     }
+    fn entity_type(&self) -> Option<&str> {
+        self.other
+            .get("assessed_entity_type")
+            .and_then(Value::as_str)
+    }
 }
 
 impl AsRef<Component> for Component {
@@ -84,6 +90,12 @@ impl EvidenceData for Evidence {
     fn database(&self) -> &str {
         &self.database
     }
+    fn year(&self) -> Option<i32> {
+        evidence_year(self.other.get("year"), self.other.get("date"))
+    }
+    fn tags(&self) -> Vec<&str> {
+        evidence_tags(self.other.get("tags"))
+    }
 }
 
 impl AsRef<Evidence> for Evidence {
@@ -101,6 +113,9 @@ pub struct Specimen {
 }
 
 impl SpecimenData for Specimen {
+    fn id(&self) -> &str {
+        &self.id
+    }
     fn loinc_code(&self) -> &str {
         &self.loinc_code
     }
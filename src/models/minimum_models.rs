@@ -1,11 +1,14 @@
 //! Minimum Models Module
 //!
-//! The minimum viable models for calculating scores. Used for 
-//! generating the external score maps in a synchronous fashion. 
+//! The minimum viable models for calculating scores. Used for
+//! generating the external score maps in a synchronous fashion.
 //! Has a reduced memory footprint.
 
 use super::traits::{BiomarkerData, ComponentData, EvidenceData, SpecimenData};
+use super::{evidence_tags, evidence_year};
 use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashSet;
 
 #[derive(Deserialize, Debug)]
 pub struct Biomarker {
@@ -15,6 +18,50 @@ pub struct Biomarker {
     pub evidence_source: Vec<Evidence>,
 }
 
+impl Biomarker {
+    /// Merges the components and evidence of another record of the same biomarker
+    /// into this one. Evidence already present (by database and ID) anywhere in
+    /// the biomarker is dropped, the condition of this record is kept. Components
+    /// assessing an entity (by ID) already assessed here are merged into the
+    /// existing component, keeping its specimens and adding new ones (by ID).
+    pub fn merge(&mut self, other: Biomarker) {
+        let mut seen: HashSet<(String, String)> = self
+            .evidence_source
+            .iter()
+            .chain(
+                self.biomarker_component
+                    .iter()
+                    .flat_map(|c| &c.evidence_source),
+            )
+            .map(Evidence::key)
+            .collect();
+        self.evidence_source.extend(
+            other
+                .evidence_source
+                .into_iter()
+                .filter(|e| seen.insert(e.key())),
+        );
+        for mut component in other.biomarker_component {
+            component.evidence_source.retain(|e| seen.insert(e.key()));
+            let existing = self.biomarker_component.iter_mut().find(|c| {
+                c.assessed_biomarker_entity_id.is_some()
+                    && c.assessed_biomarker_entity_id == component.assessed_biomarker_entity_id
+            });
+            match existing {
+                Some(existing) => {
+                    existing.evidence_source.extend(component.evidence_source);
+                    for specimen in component.specimen {
+                        if !existing.specimen.iter().any(|s| s.id == specimen.id) {
+                            existing.specimen.push(specimen);
+                        }
+                    }
+                }
+                None => self.biomarker_component.push(component),
+            }
+        }
+    }
+}
+
 impl BiomarkerData for Biomarker {
     type Component = Component;
     type Evidence = Evidence;
@@ -37,6 +84,10 @@ impl BiomarkerData for Biomarker {
 pub struct Component {
     pub specimen: Vec<Specimen>,
     pub evidence_source: Vec<Evidence>,
+    #[serde(default)]
+    pub assessed_biomarker_entity_id: Option<String>,
+    #[serde(default)]
+    pub assessed_entity_type: Option<String>,
 }
 
 impl ComponentData for Component {
@@ -49,6 +100,9 @@ impl ComponentData for Component {
     fn specimen(&self) -> &[Self::Specimen] {
         &self.specimen
     }
+    fn entity_type(&self) -> Option<&str> {
+        self.assessed_entity_type.as_deref()
+    }
 }
 
 impl AsRef<Component> for Component {
@@ -66,6 +120,19 @@ pub struct Condition {
 pub struct Evidence {
     pub id: String,
     pub database: String,
+    #[serde(default)]
+    pub year: Option<Value>,
+    #[serde(default)]
+    pub date: Option<Value>,
+    #[serde(default)]
+    pub tags: Option<Value>,
+}
+
+impl Evidence {
+    /// The evidence identity, the case insensitive database and the ID.
+    fn key(&self) -> (String, String) {
+        (self.database.trim().to_lowercase(), self.id.clone())
+    }
 }
 
 impl EvidenceData for Evidence {
@@ -75,6 +142,12 @@ impl EvidenceData for Evidence {
     fn database(&self) -> &str {
         &self.database
     }
+    fn year(&self) -> Option<i32> {
+        evidence_year(self.year.as_ref(), self.date.as_ref())
+    }
+    fn tags(&self) -> Vec<&str> {
+        evidence_tags(self.tags.as_ref())
+    }
 }
 
 impl AsRef<Evidence> for Evidence {
@@ -90,6 +163,9 @@ pub struct Specimen {
 }
 
 impl SpecimenData for Specimen {
+    fn id(&self) -> &str {
+        &self.id
+    }
     fn loinc_code(&self) -> &str {
         &self.loinc_code
     }
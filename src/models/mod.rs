@@ -1,12 +1,22 @@
 //! Models Module
 
 use crate::defaults::*;
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::fs;
+use crate::formats::{read_file, ParseError};
+use crate::rules::schema::Action;
+use crate::scores::loinc::LoincIssue;
+use crate::tables::{current_year, read_loinc_codes, read_pmid_years};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+pub use recency::{Recency, RecencyMethod};
 
 pub mod full_models;
 pub mod minimum_models;
+pub mod recency;
 pub mod traits;
 
 #[derive(Deserialize)]
@@ -20,6 +30,129 @@ pub struct Weights {
     pub loinc: Option<i32>,
     pub generic_condition_pen: Option<i32>,
     pub generic_conditions: Option<HashSet<String>>,
+    /// The number of decimal places the final score is rounded to.
+    pub precision: Option<u32>,
+    pub rounding: Option<Rounding>,
+    /// The lowest score, defaults to `MIN_SCORE`, `"none"` removes the bound.
+    pub min_score: Option<ScoreBound>,
+    /// The highest score, unbounded by default.
+    pub max_score: Option<ScoreBound>,
+    /// When the score is clamped to `min_score`/`max_score`.
+    pub clamp: Option<ClampStage>,
+    /// The optional recency criterion for PubMed evidence, disabled by default.
+    pub recency: Option<Recency>,
+    /// The weight per supported aspect (the evidence tag up to the first `:`, e.g.
+    /// `biomarker` or `specimen`), applied to every scored evidence carrying the tag.
+    pub tag_weights: Option<BTreeMap<String, f64>>,
+    /// The score for every distinct specimen (by ID, e.g. an UBERON term), disabled by default.
+    pub specimen_diversity: Option<f64>,
+    /// The cap on the distinct specimens counted, unlimited by default.
+    pub specimen_limit: Option<usize>,
+    /// The score for every component beyond the first, for panel biomarkers, disabled by default.
+    pub panel_component: Option<f64>,
+    /// The weight per assessed entity type (e.g. `protein`), applied once if any
+    /// component assesses an entity of the type.
+    pub entity_type_weights: Option<BTreeMap<String, f64>>,
+    /// Score every distinct valid LOINC code up to this cap, otherwise the LOINC score is given once.
+    pub loinc_limit: Option<usize>,
+    /// Optional table of the known LOINC codes, codes not in it are invalid.
+    pub loinc_table: Option<String>,
+    /// The contents of the `loinc_table`.
+    #[serde(skip)]
+    pub loinc_codes: Option<HashSet<String>>,
+}
+
+/// A `min_score`/`max_score` setting: a score, or `"none"` for no bound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreBound {
+    Score(f64),
+    None,
+}
+
+impl ScoreBound {
+    pub fn score(self) -> Option<f64> {
+        match self {
+            ScoreBound::Score(score) => Some(score),
+            ScoreBound::None => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ScoreBound {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Bound {
+            Score(f64),
+            Text(String),
+        }
+
+        match Bound::deserialize(deserializer)? {
+            Bound::Score(score) => Ok(ScoreBound::Score(score)),
+            Bound::Text(text) if text.eq_ignore_ascii_case("none") => Ok(ScoreBound::None),
+            Bound::Text(text) => Err(de::Error::custom(format!(
+                "expected a score or `none`, found `{}`",
+                text
+            ))),
+        }
+    }
+}
+
+/// When the score is clamped to the `min_score`/`max_score` bounds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClampStage {
+    /// Clamps the weighted score before the custom rules, so rules can move the score out of bounds.
+    #[default]
+    BeforeRules,
+    /// Clamps the final score after the custom rules.
+    AfterRules,
+    Both,
+}
+
+impl ClampStage {
+    pub fn before_rules(self) -> bool {
+        matches!(self, ClampStage::BeforeRules | ClampStage::Both)
+    }
+
+    pub fn after_rules(self) -> bool {
+        matches!(self, ClampStage::AfterRules | ClampStage::Both)
+    }
+}
+
+/// How the final score is rounded to `precision` decimal places.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rounding {
+    /// To the nearest, midpoints away from zero.
+    #[default]
+    HalfUp,
+    /// To the nearest, midpoints to the even neighbour.
+    HalfEven,
+    /// To the nearest, midpoints towards zero.
+    HalfDown,
+    /// Away from zero.
+    Up,
+    /// Towards zero.
+    Down,
+    /// Towards positive infinity.
+    Ceiling,
+    /// Towards negative infinity.
+    Floor,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfDown => RoundingStrategy::MidpointTowardZero,
+            Rounding::Up => RoundingStrategy::AwayFromZero,
+            Rounding::Down => RoundingStrategy::ToZero,
+            Rounding::Ceiling => RoundingStrategy::ToPositiveInfinity,
+            Rounding::Floor => RoundingStrategy::ToNegativeInfinity,
+        }
+    }
 }
 
 impl Default for Weights {
@@ -34,6 +167,20 @@ impl Default for Weights {
             loinc: Some(LOINC),
             generic_condition_pen: Some(GENERIC_CONDITION_PEN),
             generic_conditions: Some(GENERIC_CONDITIONS.iter().map(|&s| s.to_owned()).collect()),
+            precision: Some(PRECISION),
+            rounding: Some(Rounding::default()),
+            min_score: Some(ScoreBound::Score(MIN_SCORE)),
+            max_score: None,
+            clamp: Some(ClampStage::default()),
+            recency: None,
+            tag_weights: None,
+            specimen_diversity: None,
+            specimen_limit: None,
+            panel_component: None,
+            entity_type_weights: None,
+            loinc_limit: None,
+            loinc_table: None,
+            loinc_codes: None,
         }
     }
 }
@@ -69,54 +216,247 @@ impl Weights {
             generic_conditions: overrides
                 .and_then(|w| w.generic_conditions.clone())
                 .or(default_weights.generic_conditions),
+            precision: overrides
+                .and_then(|w| w.precision)
+                .or(default_weights.precision),
+            rounding: overrides
+                .and_then(|w| w.rounding)
+                .or(default_weights.rounding),
+            min_score: overrides
+                .and_then(|w| w.min_score)
+                .or(default_weights.min_score),
+            max_score: overrides
+                .and_then(|w| w.max_score)
+                .or(default_weights.max_score),
+            clamp: overrides.and_then(|w| w.clamp).or(default_weights.clamp),
+            recency: overrides
+                .and_then(|w| w.recency.clone())
+                .or(default_weights.recency),
+            tag_weights: overrides
+                .and_then(|w| w.tag_weights.clone())
+                .or(default_weights.tag_weights),
+            specimen_diversity: overrides
+                .and_then(|w| w.specimen_diversity)
+                .or(default_weights.specimen_diversity),
+            specimen_limit: overrides
+                .and_then(|w| w.specimen_limit)
+                .or(default_weights.specimen_limit),
+            panel_component: overrides
+                .and_then(|w| w.panel_component)
+                .or(default_weights.panel_component),
+            entity_type_weights: overrides
+                .and_then(|w| w.entity_type_weights.clone())
+                .or(default_weights.entity_type_weights),
+            loinc_limit: overrides
+                .and_then(|w| w.loinc_limit)
+                .or(default_weights.loinc_limit),
+            loinc_table: overrides
+                .and_then(|w| w.loinc_table.clone())
+                .or(default_weights.loinc_table),
+            loinc_codes: None,
+        }
+    }
+
+    /// Resolves the relative table paths against the base directory.
+    pub fn resolve_paths(&mut self, base: &Path) {
+        let pmid_years = self
+            .recency
+            .as_mut()
+            .and_then(|recency| recency.pmid_years.as_mut());
+        for path in pmid_years.into_iter().chain(self.loinc_table.as_mut()) {
+            if Path::new(path.as_str()).is_relative() {
+                *path = base.join(path.as_str()).to_string_lossy().into_owned();
+            }
         }
     }
+
+    /// Reads the lookup tables the weights refer to and fills in the defaults
+    /// that depend on the time of the run.
+    pub fn load_tables(mut self) -> Result<Self, ParseError> {
+        if let Some(recency) = self.recency.as_mut() {
+            if let Some(path) = &recency.pmid_years {
+                recency.years = read_pmid_years(path)?;
+            }
+            recency.reference_year = recency.reference_year.or(Some(current_year()));
+        }
+        if let Some(path) = &self.loinc_table {
+            self.loinc_codes = Some(read_loinc_codes(path)?);
+        }
+        Ok(self)
+    }
+
+    /// The lowest score, `None` if unbounded.
+    pub fn min_score(&self) -> Option<f64> {
+        self.min_score.and_then(ScoreBound::score)
+    }
+
+    /// The highest score, `None` if unbounded.
+    pub fn max_score(&self) -> Option<f64> {
+        self.max_score.and_then(ScoreBound::score)
+    }
+
+    /// Rounds the score to the configured precision with the configured rounding mode.
+    pub fn round(&self, score: Decimal) -> Decimal {
+        score.round_dp_with_strategy(
+            self.precision.unwrap_or(PRECISION),
+            self.rounding.unwrap_or_default().strategy(),
+        )
+    }
+}
+
+/// The publication year of an evidence from its `year` (a number or a string)
+/// or the leading year of its `date` (a string like `2020-01-31` or a number
+/// like `20200131`).
+pub(crate) fn evidence_year(year: Option<&Value>, date: Option<&Value>) -> Option<i32> {
+    let parse = |year: &str| year.trim().get(..4).and_then(|y| y.parse().ok());
+    match year {
+        Some(Value::Number(year)) => year.as_i64().map(|y| y as i32),
+        Some(Value::String(year)) => parse(year),
+        _ => None,
+    }
+    .or_else(|| match date {
+        Some(Value::Number(date)) => parse(&date.to_string()),
+        Some(Value::String(date)) => parse(date),
+        _ => None,
+    })
+}
+
+/// The tags of an evidence, skipping a `tags` value that isn't a list and
+/// entries without a string `tag`.
+pub(crate) fn evidence_tags(tags: Option<&Value>) -> Vec<&str> {
+    tags.and_then(Value::as_array)
+        .map(|tags| {
+            tags.iter()
+                .filter_map(|tag| tag.get("tag").and_then(Value::as_str))
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
-pub fn get_weights_overrides(overrides_file: Option<&String>) -> Weights {
+/// Converts a weight or rule value to a decimal, NaN and infinities become zero.
+pub(crate) fn to_decimal(value: f64) -> Decimal {
+    Decimal::from_f64(value).unwrap_or_default()
+}
+
+/// Converts a decimal score back to the `f64` used in the output.
+pub(crate) fn to_f64(value: Decimal) -> f64 {
+    value.to_f64().unwrap_or_default()
+}
+
+/// Reads the weight overrides file (JSON, YAML or TOML by file extension) and
+/// merges it with the default weights. Relative table paths are resolved
+/// against the directory of the overrides file.
+pub fn get_weights_overrides(overrides_file: Option<&String>) -> Result<Weights, ParseError> {
     if let Some(path) = overrides_file {
-        let file_contents = fs::read_to_string(path).expect("Could not read overrides file.");
-        let overrides =
-            serde_json::from_str(&file_contents).expect("Error parsing overrides file.");
-        Weights::with_defaults(Some(&overrides))
+        let mut overrides: Weights = read_file(path)?;
+        overrides.resolve_paths(Path::new(path).parent().unwrap_or(Path::new("")));
+        Weights::with_defaults(Some(&overrides)).load_tables()
     } else {
-        Weights::with_defaults(None)
+        Ok(Weights::with_defaults(None))
     }
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BiomarkerScore {
     pub score: f64,
     pub score_info: ScoreInfo,
+    /// The keys of the source files the biomarker was aggregated from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Vec<String>>,
+    /// The score normalised to 0-1, when normalisation is enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalized_score: Option<f64>,
+    /// The percentile rank of the score within the run, when normalisation is enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentile: Option<f64>,
+    /// The tier label of the normalised score, when tiers are configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tier: Option<String>,
+}
+
+impl BiomarkerScore {
+    pub fn new(score: f64, score_info: ScoreInfo) -> Self {
+        BiomarkerScore {
+            score,
+            score_info,
+            provenance: None,
+            normalized_score: None,
+            percentile: None,
+            tier: None,
+        }
+    }
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ScoreContribution {
     pub c: String,
     pub w: f64,
     pub f: f64,
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ScoreInfo {
     pub contributions: Vec<ScoreContribution>,
     pub formula: String,
-    pub variables: HashMap<String, String>,
+    pub variables: BTreeMap<String, String>,
+    /// The score before any custom rules are applied.
+    #[serde(default)]
+    pub base_score: f64,
+    /// The score bounds and how much clamping to them changed the score.
+    #[serde(default)]
+    pub clamp: Option<ScoreClamp>,
     pub custom_rules: Option<Vec<CustomRuleApplication>>,
+    /// The problems found in the biomarker data, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_quality: Option<DataQuality>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DataQuality {
+    /// The LOINC codes that were not scored, in the order found.
+    pub invalid_loinc_codes: Vec<InvalidLoinc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InvalidLoinc {
+    /// The component index of the specimen.
+    pub component: usize,
+    pub specimen_id: String,
+    pub loinc_code: String,
+    pub issue: LoincIssue,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScoreClamp {
+    pub min_score: Option<f64>,
+    pub max_score: Option<f64>,
+    pub stage: ClampStage,
+    /// The score change from clamping before the custom rules, `None` if the stage doesn't clamp there.
+    pub before_rules: Option<f64>,
+    /// The score change from clamping after the custom rules, `None` if the stage doesn't clamp there.
+    pub after_rules: Option<f64>,
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CustomRuleApplication {
     pub rule_name: String,
+    pub priority: i32,
     pub condition: CustomCondition,
-    pub action: String,
+    pub action: Action,
     pub effect: f64,
 }
 
-#[derive(Serialize, Debug, Clone)]
-#[serde(tag = "type", content = "content")]
-pub enum CustomCondition {
-    Simple(String),
-    And(Vec<CustomCondition>),
-    Or(Vec<CustomCondition>),
+/// Structured description of a rule condition, mirroring the input rule schema
+/// and recording whether each (sub-)condition matched the biomarker.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CustomCondition {
+    #[serde(rename = "type")]
+    pub condition_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conditions: Option<Vec<CustomCondition>>,
+    pub matched: bool,
 }
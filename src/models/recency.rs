@@ -0,0 +1,64 @@
+//! Recency Module
+//!
+//! The optional recency criterion, weighting PubMed evidence by the age of
+//! the publication.
+
+use crate::defaults::*;
+use crate::tables::current_year;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// How the age of the evidence is turned into a frequency.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecencyMethod {
+    /// Every publication counts `0.5^(age / half_life)`.
+    #[default]
+    Decay,
+    /// Every publication younger than `window` years counts 1.
+    Bonus,
+}
+
+/// The recency criterion settings.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Recency {
+    #[serde(default)]
+    pub method: RecencyMethod,
+    /// The weight of the `recency` contribution, defaults to `RECENCY_WEIGHT`.
+    pub weight: Option<f64>,
+    /// The age in years at which a publication counts half, defaults to `RECENCY_HALF_LIFE`.
+    pub half_life: Option<f64>,
+    /// The age in years below which a publication earns the bonus, defaults to `RECENCY_WINDOW`.
+    pub window: Option<i32>,
+    /// The year the ages are counted from, defaults to the current year.
+    pub reference_year: Option<i32>,
+    /// Optional PMID to publication year table, used for evidence without a year or date.
+    pub pmid_years: Option<String>,
+    /// The contents of the `pmid_years` table.
+    #[serde(skip)]
+    pub years: HashMap<String, i32>,
+}
+
+impl Recency {
+    /// The publication year of the evidence, from the evidence itself or the PMID table.
+    pub fn year(&self, evidence_year: Option<i32>, pmid: &str) -> Option<i32> {
+        evidence_year.or_else(|| self.years.get(pmid).copied())
+    }
+
+    /// The frequency a publication of the year counts for, 0 if the year is unknown.
+    /// Publications dated after the reference year count as published in it.
+    pub fn factor(&self, year: Option<i32>) -> f64 {
+        let Some(year) = year else {
+            return 0.0;
+        };
+        let reference_year = self.reference_year.unwrap_or_else(current_year);
+        let age = (reference_year - year).max(0);
+        match self.method {
+            RecencyMethod::Decay => {
+                0.5_f64.powf(age as f64 / self.half_life.unwrap_or(RECENCY_HALF_LIFE))
+            }
+            RecencyMethod::Bonus if age < self.window.unwrap_or(RECENCY_WINDOW) => 1.0,
+            RecencyMethod::Bonus => 0.0,
+        }
+    }
+}
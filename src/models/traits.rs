@@ -17,13 +17,21 @@ pub trait ComponentData {
 
     fn evidence_source(&self) -> &[Self::Evidence];
     fn specimen(&self) -> &[Self::Specimen];
+    /// The type of the assessed biomarker entity, e.g. `protein`.
+    fn entity_type(&self) -> Option<&str>;
 }
 
 pub trait EvidenceData {
     fn id(&self) -> &str;
     fn database(&self) -> &str;
+    /// The publication year, from the `year` or `date` of the evidence.
+    fn year(&self) -> Option<i32>;
+    /// The tags naming what the evidence supports, e.g. `biomarker` or `specimen:UBERON:0001088`.
+    fn tags(&self) -> Vec<&str>;
 }
 
 pub trait SpecimenData {
+    /// The specimen ID, usually an UBERON term.
+    fn id(&self) -> &str;
     fn loinc_code(&self) -> &str;
 }
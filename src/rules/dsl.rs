@@ -0,0 +1,747 @@
+//! DSL Module
+//!
+//! A small text language for writing custom rules, parsed into the same
+//! structures as the JSON rules format. For example:
+//!
+//! ```text
+//! # Comments start with a hash
+//! rule "clinvar only" priority 1:
+//!     non_pubmed(top_source) == "clinvar" and len(loinc) > 0
+//!     => add 2
+//! ```
+//!
+//! Conditions:
+//!
+//! - `non_pubmed(field) == "value"`: `NonPubmedEvidenceSourceMatch`
+//! - `field == "value"`: `FieldEquals`
+//! - `all(field) contains "value"`: `FieldAllContains`
+//! - `any(field) contains "value"`: `FieldSomeContains`
+//! - `len(field) > n`, `len(field) < n`, `len(field) == n`: `FieldLenGreaterThan`,
+//!   `FieldLenLessThan` and `FieldLenEqual`
+//! - `a and b`, `a or b`, `(a)`: `And` and `Or`, `and` binds tighter than `or`
+//! - `true`, `false`: an empty `And` and an empty `Or`
+//!
+//! Fields are `biomarker_id`, `component_source`, `condition_id`, `top_source`
+//! and `loinc`, or their field paths (e.g. `condition.id`).
+//!
+//! Actions are `set`, `add`, `subtract`, `multiply` and `divide` followed by a number.
+
+use crate::rules::schema::{Action, Condition, CustomRules, Field, Rule};
+use std::fmt;
+
+/// A DSL syntax error with its 1-based location.
+#[derive(Debug)]
+pub struct DslError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for DslError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for DslError {}
+
+/// Parses the DSL source into custom rules.
+pub fn parse_dsl(source: &str) -> Result<CustomRules, DslError> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let mut rules = Vec::new();
+    while !parser.at_end() {
+        rules.push(parser.rule()?);
+    }
+    Ok(CustomRules {
+        rules,
+        tests: Vec::new(),
+    })
+}
+
+/// Formats the custom rules as DSL source. Rule tests can't be represented in
+/// the DSL and are not included.
+pub fn format_dsl(rules: &CustomRules) -> String {
+    let mut output = String::new();
+    for (idx, rule) in rules.rules.iter().enumerate() {
+        if idx > 0 {
+            output.push('\n');
+        }
+        output.push_str(&format!(
+            "rule {} priority {}:\n    {}\n    => {}\n",
+            quote(&rule.name),
+            rule.priority,
+            format_condition(&rule.condition),
+            format_action(&rule.action)
+        ));
+    }
+    output
+}
+
+fn format_condition(condition: &Condition) -> String {
+    match condition {
+        Condition::NonPubmedEvidenceSourceMatch { field, value } => {
+            format!("non_pubmed({}) == {}", field_name(field), quote(value))
+        }
+        Condition::FieldEquals { field, value } => {
+            format!("{} == {}", field_name(field), quote(value))
+        }
+        Condition::FieldAllContains { field, value } => {
+            format!("all({}) contains {}", field_name(field), quote(value))
+        }
+        Condition::FieldSomeContains { field, value } => {
+            format!("any({}) contains {}", field_name(field), quote(value))
+        }
+        Condition::FieldLenGreaterThan { field, value } => {
+            format!("len({}) > {}", field_name(field), value)
+        }
+        Condition::FieldLenLessThan { field, value } => {
+            format!("len({}) < {}", field_name(field), value)
+        }
+        Condition::FieldLenEqual { field, value } => {
+            format!("len({}) == {}", field_name(field), value)
+        }
+        Condition::And { conditions } if conditions.is_empty() => "true".to_owned(),
+        Condition::Or { conditions } if conditions.is_empty() => "false".to_owned(),
+        Condition::And { conditions } => conditions
+            .iter()
+            .map(|c| match c {
+                Condition::And { conditions } | Condition::Or { conditions }
+                    if !conditions.is_empty() =>
+                {
+                    format!("({})", format_condition(c))
+                }
+                _ => format_condition(c),
+            })
+            .collect::<Vec<_>>()
+            .join(" and "),
+        Condition::Or { conditions } => conditions
+            .iter()
+            .map(|c| match c {
+                Condition::Or { conditions } if !conditions.is_empty() => {
+                    format!("({})", format_condition(c))
+                }
+                _ => format_condition(c),
+            })
+            .collect::<Vec<_>>()
+            .join(" or "),
+    }
+}
+
+fn format_action(action: &Action) -> String {
+    match action {
+        Action::SetScore(value) => format!("set {}", value),
+        Action::AddToScore(value) => format!("add {}", value),
+        Action::SubtractScore(value) => format!("subtract {}", value),
+        Action::MultiplyScore(value) => format!("multiply {}", value),
+        Action::DivideScore(value) => format!("divide {}", value),
+    }
+}
+
+fn field_name(field: &Field) -> &'static str {
+    match field {
+        Field::BiomarkerID => "biomarker_id",
+        Field::ComponentEvidenceSourceDatabase => "component_source",
+        Field::ConditionID => "condition_id",
+        Field::TopEvidenceSourceDatabase => "top_source",
+        Field::LoincCode => "loinc",
+    }
+}
+
+fn parse_field(name: &str) -> Option<Field> {
+    let fields = [
+        Field::BiomarkerID,
+        Field::ComponentEvidenceSourceDatabase,
+        Field::ConditionID,
+        Field::TopEvidenceSourceDatabase,
+        Field::LoincCode,
+    ];
+    fields
+        .into_iter()
+        .find(|f| field_name(f) == name || f.as_str() == name)
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    LParen,
+    RParen,
+    Colon,
+    Eq,
+    Gt,
+    Lt,
+    Arrow,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, DslError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_column) = (line, column);
+        let error = |message: String| DslError {
+            line: start_line,
+            column: start_column,
+            message,
+        };
+        let mut advance = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let c = chars.next();
+            if c == Some('\n') {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+            c
+        };
+
+        let kind = match c {
+            c if c.is_whitespace() => {
+                advance(&mut chars);
+                continue;
+            }
+            '#' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    advance(&mut chars);
+                }
+                continue;
+            }
+            '(' | ')' | ':' | '>' | '<' => {
+                advance(&mut chars);
+                match c {
+                    '(' => TokenKind::LParen,
+                    ')' => TokenKind::RParen,
+                    ':' => TokenKind::Colon,
+                    '>' => TokenKind::Gt,
+                    _ => TokenKind::Lt,
+                }
+            }
+            '=' => {
+                advance(&mut chars);
+                match advance(&mut chars) {
+                    Some('=') => TokenKind::Eq,
+                    Some('>') => TokenKind::Arrow,
+                    _ => return Err(error("expected `==` or `=>`".to_owned())),
+                }
+            }
+            '"' => {
+                advance(&mut chars);
+                let mut value = String::new();
+                loop {
+                    match advance(&mut chars) {
+                        Some('"') => break,
+                        Some('\\') => match advance(&mut chars) {
+                            Some(escaped @ ('"' | '\\')) => value.push(escaped),
+                            Some('n') => value.push('\n'),
+                            _ => return Err(error("invalid escape in string".to_owned())),
+                        },
+                        Some(c) => value.push(c),
+                        None => return Err(error("unterminated string".to_owned())),
+                    }
+                }
+                TokenKind::Str(value)
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let mut number = String::new();
+                while chars.peek().is_some_and(|&c| {
+                    c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')
+                }) {
+                    number.push(advance(&mut chars).unwrap());
+                }
+                let value = number
+                    .parse()
+                    .map_err(|_| error(format!("invalid number `{}`", number)))?;
+                TokenKind::Num(value)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while chars
+                    .peek()
+                    .is_some_and(|&c| c.is_alphanumeric() || c == '_' || c == '.')
+                {
+                    ident.push(advance(&mut chars).unwrap());
+                }
+                TokenKind::Ident(ident)
+            }
+            c => return Err(error(format!("unexpected character `{}`", c))),
+        };
+        tokens.push(Token {
+            kind,
+            line: start_line,
+            column: start_column,
+        });
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    fn error(&self, message: String) -> DslError {
+        let (line, column) = match self.tokens.get(self.pos).or(self.tokens.last()) {
+            Some(token) => (token.line, token.column),
+            None => (1, 1),
+        };
+        DslError {
+            line,
+            column,
+            message,
+        }
+    }
+
+    fn next(&mut self, expected: &str) -> Result<TokenKind, DslError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.kind.clone())
+            }
+            None => Err(self.error(format!("expected {}, found end of input", expected))),
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind, expected: &str) -> Result<(), DslError> {
+        if self.peek() == Some(&kind) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {}", expected)))
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), DslError> {
+        match self.peek() {
+            Some(TokenKind::Ident(ident)) if ident == keyword => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error(format!("expected `{}`", keyword))),
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(TokenKind::Ident(ident)) if ident == keyword)
+    }
+
+    fn string(&mut self) -> Result<String, DslError> {
+        match self.peek() {
+            Some(TokenKind::Str(value)) => {
+                let value = value.clone();
+                self.pos += 1;
+                Ok(value)
+            }
+            _ => Err(self.error("expected a string".to_owned())),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, DslError> {
+        match self.peek() {
+            Some(TokenKind::Num(value)) => {
+                let value = *value;
+                self.pos += 1;
+                Ok(value)
+            }
+            _ => Err(self.error("expected a number".to_owned())),
+        }
+    }
+
+    fn field(&mut self) -> Result<Field, DslError> {
+        match self.peek() {
+            Some(TokenKind::Ident(name)) => match parse_field(name) {
+                Some(field) => {
+                    self.pos += 1;
+                    Ok(field)
+                }
+                None => Err(self.error(format!("unknown field `{}`", name))),
+            },
+            _ => Err(self.error("expected a field".to_owned())),
+        }
+    }
+
+    fn call_field(&mut self) -> Result<Field, DslError> {
+        self.expect(TokenKind::LParen, "`(`")?;
+        let field = self.field()?;
+        self.expect(TokenKind::RParen, "`)`")?;
+        Ok(field)
+    }
+
+    fn rule(&mut self) -> Result<Rule, DslError> {
+        self.keyword("rule")?;
+        let name = self.string()?;
+        self.keyword("priority")?;
+        let priority = self.number()?;
+        if priority.fract() != 0.0 {
+            self.pos -= 1;
+            return Err(self.error("expected an integer priority".to_owned()));
+        }
+        self.expect(TokenKind::Colon, "`:`")?;
+        let condition = self.or_condition()?;
+        self.expect(TokenKind::Arrow, "`=>`")?;
+        let action = self.action()?;
+        Ok(Rule {
+            name,
+            condition,
+            action,
+            priority: priority as i32,
+        })
+    }
+
+    fn or_condition(&mut self) -> Result<Condition, DslError> {
+        let mut conditions = vec![self.and_condition()?];
+        while self.is_keyword("or") {
+            self.pos += 1;
+            conditions.push(self.and_condition()?);
+        }
+        Ok(if conditions.len() == 1 {
+            conditions.remove(0)
+        } else {
+            Condition::Or { conditions }
+        })
+    }
+
+    fn and_condition(&mut self) -> Result<Condition, DslError> {
+        let mut conditions = vec![self.primary_condition()?];
+        while self.is_keyword("and") {
+            self.pos += 1;
+            conditions.push(self.primary_condition()?);
+        }
+        Ok(if conditions.len() == 1 {
+            conditions.remove(0)
+        } else {
+            Condition::And { conditions }
+        })
+    }
+
+    fn primary_condition(&mut self) -> Result<Condition, DslError> {
+        if self.peek() == Some(&TokenKind::LParen) {
+            self.pos += 1;
+            let condition = self.or_condition()?;
+            self.expect(TokenKind::RParen, "`)`")?;
+            return Ok(condition);
+        }
+        let start = self.pos;
+        let TokenKind::Ident(ident) = self.next("a condition")? else {
+            self.pos = start;
+            return Err(self.error("expected a condition".to_owned()));
+        };
+        match ident.as_str() {
+            "true" => Ok(Condition::And {
+                conditions: Vec::new(),
+            }),
+            "false" => Ok(Condition::Or {
+                conditions: Vec::new(),
+            }),
+            "non_pubmed" => {
+                let field = self.call_field()?;
+                self.expect(TokenKind::Eq, "`==`")?;
+                let value = self.string()?;
+                Ok(Condition::NonPubmedEvidenceSourceMatch { field, value })
+            }
+            "all" | "any" => {
+                let field = self.call_field()?;
+                self.keyword("contains")?;
+                let value = self.string()?;
+                Ok(if ident == "all" {
+                    Condition::FieldAllContains { field, value }
+                } else {
+                    Condition::FieldSomeContains { field, value }
+                })
+            }
+            "len" => {
+                let field = self.call_field()?;
+                let operator = self.next("`>`, `<` or `==`")?;
+                let value = self.number()?;
+                match operator {
+                    TokenKind::Gt => Ok(Condition::FieldLenGreaterThan { field, value }),
+                    TokenKind::Lt => Ok(Condition::FieldLenLessThan { field, value }),
+                    TokenKind::Eq => Ok(Condition::FieldLenEqual { field, value }),
+                    _ => {
+                        self.pos -= 2;
+                        Err(self.error("expected `>`, `<` or `==`".to_owned()))
+                    }
+                }
+            }
+            _ => {
+                self.pos = start;
+                let field = self.field()?;
+                self.expect(TokenKind::Eq, "`==`")?;
+                let value = self.string()?;
+                Ok(Condition::FieldEquals { field, value })
+            }
+        }
+    }
+
+    fn action(&mut self) -> Result<Action, DslError> {
+        let start = self.pos;
+        let TokenKind::Ident(ident) = self.next("an action")? else {
+            self.pos = start;
+            return Err(self.error("expected an action".to_owned()));
+        };
+        let value = self.number()?;
+        match ident.as_str() {
+            "set" => Ok(Action::SetScore(value)),
+            "add" => Ok(Action::AddToScore(value)),
+            "subtract" => Ok(Action::SubtractScore(value)),
+            "multiply" => Ok(Action::MultiplyScore(value)),
+            "divide" => Ok(Action::DivideScore(value)),
+            _ => {
+                self.pos = start;
+                Err(self.error(format!("unknown action `{}`", ident)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn parse_condition(condition: &str) -> Value {
+        let source = format!("rule \"r\" priority 0:\n    {}\n    => add 1\n", condition);
+        let rules = parse_dsl(&source).unwrap();
+        serde_json::to_value(&rules.rules[0].condition).unwrap()
+    }
+
+    fn parse_error(source: &str) -> DslError {
+        match parse_dsl(source) {
+            Ok(_) => panic!("expected a parse error for {:?}", source),
+            Err(error) => error,
+        }
+    }
+
+    fn leaf(value: &str) -> Condition {
+        Condition::FieldEquals {
+            field: Field::BiomarkerID,
+            value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let a = json!({ "type": "FieldEquals", "field": "BiomarkerID", "value": "a" });
+        let b = json!({ "type": "FieldEquals", "field": "BiomarkerID", "value": "b" });
+        let c = json!({ "type": "FieldEquals", "field": "BiomarkerID", "value": "c" });
+
+        assert_eq!(
+            parse_condition(
+                r#"biomarker_id == "a" or biomarker_id == "b" and biomarker_id == "c""#
+            ),
+            json!({ "type": "Or", "conditions": [a, { "type": "And", "conditions": [b, c] }] })
+        );
+        assert_eq!(
+            parse_condition(
+                r#"(biomarker_id == "a" or biomarker_id == "b") and biomarker_id == "c""#
+            ),
+            json!({ "type": "And", "conditions": [{ "type": "Or", "conditions": [a, b] }, c] })
+        );
+    }
+
+    #[test]
+    fn nested_conditions_are_parenthesized() {
+        let or = Condition::Or {
+            conditions: vec![leaf("a"), leaf("b")],
+        };
+        let and = Condition::And {
+            conditions: vec![leaf("c"), leaf("d")],
+        };
+
+        // An `Or` inside an `And` needs parentheses
+        assert_eq!(
+            format_condition(&Condition::And {
+                conditions: vec![or.clone(), leaf("c")],
+            }),
+            r#"(biomarker_id == "a" or biomarker_id == "b") and biomarker_id == "c""#
+        );
+        // An `And` inside an `Or` doesn't, a nested `Or` keeps its grouping
+        assert_eq!(
+            format_condition(&Condition::Or {
+                conditions: vec![and, or],
+            }),
+            r#"biomarker_id == "c" and biomarker_id == "d" or (biomarker_id == "a" or biomarker_id == "b")"#
+        );
+        assert_eq!(
+            format_condition(&Condition::And {
+                conditions: Vec::new()
+            }),
+            "true"
+        );
+        assert_eq!(
+            format_condition(&Condition::Or {
+                conditions: Vec::new()
+            }),
+            "false"
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            parse_condition(r#"biomarker_id == "a \"quoted\" \\ back\nslash""#)["value"],
+            json!("a \"quoted\" \\ back\nslash")
+        );
+        assert_eq!(quote("say \"hi\" \\"), r#""say \"hi\" \\""#);
+
+        let error = parse_error("rule \"bad \\t\" priority 0:");
+        assert_eq!((error.line, error.column), (1, 6));
+        assert_eq!(error.message, "invalid escape in string");
+    }
+
+    #[test]
+    fn negative_and_exponent_numbers() {
+        assert_eq!(parse_condition("len(loinc) > -1")["value"], json!(-1.0));
+        assert_eq!(parse_condition("len(loinc) < 1e3")["value"], json!(1000.0));
+        assert_eq!(
+            parse_condition("len(loinc) == 2.5E-1")["value"],
+            json!(0.25)
+        );
+
+        let rules = parse_dsl("rule \"r\" priority -2:\n    true\n    => set -1.5e+1\n").unwrap();
+        assert_eq!(rules.rules[0].priority, -2);
+        assert_eq!(
+            serde_json::to_value(&rules.rules[0].action).unwrap(),
+            json!({ "type": "SetScore", "value": -15.0 })
+        );
+    }
+
+    #[test]
+    fn error_locations() {
+        let error = parse_error("rule \"r\" priority 0:\n    len(loinc) >= 1\n");
+        assert_eq!((error.line, error.column), (2, 17));
+
+        let error = parse_error("rule \"r\" priority 0:\n    unknown == \"a\"\n");
+        assert_eq!((error.line, error.column), (2, 5));
+        assert_eq!(error.message, "unknown field `unknown`");
+
+        let error = parse_error("rule \"r\" priority 0:\n    true\n    => add\n");
+        assert_eq!((error.line, error.column), (3, 8));
+        assert_eq!(error.message, "expected a number");
+
+        let error = parse_error("rule \"r\" priority 1.5:");
+        assert_eq!((error.line, error.column), (1, 19));
+
+        let error = parse_error("rule \"r\n");
+        assert_eq!((error.line, error.column), (1, 6));
+        assert_eq!(error.message, "unterminated string");
+    }
+
+    #[test]
+    fn format_parse_round_trip() {
+        let fields = [
+            Field::BiomarkerID,
+            Field::ComponentEvidenceSourceDatabase,
+            Field::ConditionID,
+            Field::TopEvidenceSourceDatabase,
+            Field::LoincCode,
+        ];
+        let mut conditions: Vec<Condition> = Vec::new();
+        for field in fields {
+            conditions.push(Condition::NonPubmedEvidenceSourceMatch {
+                field: field.clone(),
+                value: "clinvar".to_owned(),
+            });
+            conditions.push(Condition::FieldEquals {
+                field: field.clone(),
+                value: "a \"b\" \\ c".to_owned(),
+            });
+            conditions.push(Condition::FieldAllContains {
+                field: field.clone(),
+                value: "x".to_owned(),
+            });
+            conditions.push(Condition::FieldSomeContains {
+                field: field.clone(),
+                value: "y".to_owned(),
+            });
+            conditions.push(Condition::FieldLenGreaterThan {
+                field: field.clone(),
+                value: 2.0,
+            });
+            conditions.push(Condition::FieldLenLessThan {
+                field: field.clone(),
+                value: -0.5,
+            });
+            conditions.push(Condition::FieldLenEqual { field, value: 1e-7 });
+        }
+        conditions.push(Condition::And {
+            conditions: Vec::new(),
+        });
+        conditions.push(Condition::Or {
+            conditions: Vec::new(),
+        });
+        conditions.push(Condition::Or {
+            conditions: vec![
+                Condition::And {
+                    conditions: vec![leaf("a"), leaf("b")],
+                },
+                Condition::Or {
+                    conditions: vec![leaf("c"), leaf("d")],
+                },
+            ],
+        });
+        conditions.push(Condition::And {
+            conditions: vec![
+                Condition::Or {
+                    conditions: vec![leaf("a"), leaf("b")],
+                },
+                Condition::And {
+                    conditions: vec![leaf("c"), leaf("d")],
+                },
+            ],
+        });
+        let actions = [
+            Action::SetScore(-5.0),
+            Action::AddToScore(2.0),
+            Action::SubtractScore(0.25),
+            Action::MultiplyScore(1.1),
+            Action::DivideScore(3.0),
+        ];
+
+        let rules = CustomRules {
+            rules: conditions
+                .into_iter()
+                .enumerate()
+                .map(|(idx, condition)| Rule {
+                    name: format!("rule \"{}\"", idx),
+                    condition,
+                    action: actions[idx % actions.len()].clone(),
+                    priority: idx as i32 - 3,
+                })
+                .collect(),
+            tests: Vec::new(),
+        };
+        let parsed = parse_dsl(&format_dsl(&rules)).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed.rules).unwrap(),
+            serde_json::to_value(&rules.rules).unwrap()
+        );
+    }
+}
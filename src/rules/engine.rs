@@ -1,12 +1,37 @@
 use crate::models::traits::{BiomarkerData, ComponentData, EvidenceData, SpecimenData};
-use crate::models::{CustomCondition, CustomRuleApplication};
+use crate::models::{to_decimal, to_f64, CustomCondition, CustomRuleApplication};
 use crate::rules::schema::{Action, Condition, CustomRules, Field};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// A custom rule evaluation in the trace of a score computation.
+#[derive(Serialize, Debug, Clone)]
+pub struct RuleStep {
+    pub rule_name: String,
+    pub priority: i32,
+    pub condition: CustomCondition,
+    pub action: Action,
+    /// The effect on the score, `None` if the condition did not match.
+    pub effect: Option<f64>,
+    pub running_score: f64,
+}
 
 pub fn apply_custom_rules<B: BiomarkerData>(
     biomarker: &B,
     rules: &CustomRules,
-    current_score: f64,
-) -> (f64, Vec<CustomRuleApplication>) {
+    current_score: Decimal,
+) -> (Decimal, Vec<CustomRuleApplication>) {
+    apply_custom_rules_traced(biomarker, rules, current_score, None)
+}
+
+/// Applies the custom rules, recording every rule evaluation (matched or not)
+/// in `trace` if provided.
+pub(crate) fn apply_custom_rules_traced<B: BiomarkerData>(
+    biomarker: &B,
+    rules: &CustomRules,
+    current_score: Decimal,
+    mut trace: Option<&mut Vec<RuleStep>>,
+) -> (Decimal, Vec<CustomRuleApplication>) {
     let mut score = current_score;
     let mut applied_rules = Vec::new();
 
@@ -17,21 +42,35 @@ pub fn apply_custom_rules<B: BiomarkerData>(
     preprocess_conditions(&conditions);
 
     for rule in sorted_rules.iter() {
-        if evaluate_condition(biomarker, &rule.condition) {
-            let (new_score, effect) = apply_action(score, &rule.action);
+        let matched = evaluate_condition(biomarker, &rule.condition);
+        let mut effect = None;
+        if matched {
+            let (new_score, rule_effect) = apply_action(score, &rule.action);
             score = new_score;
+            effect = Some(rule_effect);
             applied_rules.push(CustomRuleApplication {
                 rule_name: rule.name.clone(),
-                condition: condition_to_custom_condition(&rule.condition),
-                action: format!("{:?}", rule.action),
-                effect,
+                priority: rule.priority,
+                condition: describe_condition(biomarker, &rule.condition),
+                action: rule.action.clone(),
+                effect: to_f64(rule_effect),
             })
         }
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.push(RuleStep {
+                rule_name: rule.name.clone(),
+                priority: rule.priority,
+                condition: describe_condition(biomarker, &rule.condition),
+                action: rule.action.clone(),
+                effect: effect.map(to_f64),
+                running_score: to_f64(score),
+            });
+        }
     }
     (score, applied_rules)
 }
 
-fn evaluate_condition<B: BiomarkerData>(biomarker: &B, condition: &Condition) -> bool {
+pub(crate) fn evaluate_condition<B: BiomarkerData>(biomarker: &B, condition: &Condition) -> bool {
     match condition {
         Condition::NonPubmedEvidenceSourceMatch { field, value } => {
             let matched_sources: Vec<String> = match_field(biomarker, field)
@@ -72,13 +111,20 @@ fn evaluate_condition<B: BiomarkerData>(biomarker: &B, condition: &Condition) ->
     }
 }
 
-fn apply_action(score: f64, action: &Action) -> (f64, f64) {
-    match action {
-        Action::SetScore(value) => (*value, *value - score),
-        Action::AddToScore(value) => (score + value, *value),
-        Action::MultiplyScore(value) => (score * value, score * (value - 1.0)),
-        Action::SubtractScore(value) => (score - value, -*value),
-        Action::DivideScore(value) => (score / value, score * (1.0 / value - 1.0)),
+/// Applies the action to the score, returning the new score and the effect.
+/// An action that can't be applied (a division by zero or an overflow) leaves
+/// the score unchanged.
+pub(crate) fn apply_action(score: Decimal, action: &Action) -> (Decimal, Decimal) {
+    let new_score = match action {
+        Action::SetScore(value) => Some(to_decimal(*value)),
+        Action::AddToScore(value) => score.checked_add(to_decimal(*value)),
+        Action::MultiplyScore(value) => score.checked_mul(to_decimal(*value)),
+        Action::SubtractScore(value) => score.checked_sub(to_decimal(*value)),
+        Action::DivideScore(value) => score.checked_div(to_decimal(*value)),
+    };
+    match new_score {
+        Some(new_score) => (new_score, new_score - score),
+        None => (score, Decimal::ZERO),
     }
 }
 
@@ -106,25 +152,34 @@ fn match_field<B: BiomarkerData>(biomarker: &B, field: &Field) -> Vec<String> {
     }
 }
 
-fn condition_to_custom_condition(condition: &Condition) -> CustomCondition {
-    match condition {
-        Condition::And { conditions } => CustomCondition::And(
-            conditions
+/// Builds the structured description of a condition, evaluating every
+/// sub-condition of an `And`/`Or` so each leaf reports whether it matched.
+fn describe_condition<B: BiomarkerData>(biomarker: &B, condition: &Condition) -> CustomCondition {
+    let (conditions, matched) = match condition {
+        Condition::And { conditions } | Condition::Or { conditions } => {
+            let described: Vec<CustomCondition> = conditions
                 .iter()
-                .map(condition_to_custom_condition)
-                .collect(),
-        ),
-        Condition::Or { conditions } => CustomCondition::Or(
-            conditions
-                .iter()
-                .map(condition_to_custom_condition)
-                .collect(),
-        ),
-        _ => CustomCondition::Simple(format!("{:?}", condition)),
+                .map(|c| describe_condition(biomarker, c))
+                .collect();
+            let matched = if matches!(condition, Condition::And { .. }) {
+                described.iter().all(|c| c.matched)
+            } else {
+                described.iter().any(|c| c.matched)
+            };
+            (Some(described), matched)
+        }
+        _ => (None, evaluate_condition(biomarker, condition)),
+    };
+    CustomCondition {
+        condition_type: condition.type_name().to_owned(),
+        field: condition.field().map(|f| f.as_str().to_owned()),
+        value: condition.value(),
+        conditions,
+        matched,
     }
 }
 
-fn preprocess_conditions(conditions: &Vec<Condition>) {
+fn preprocess_conditions(conditions: &[Condition]) {
     for condition in conditions.iter() {
         match condition {
             Condition::NonPubmedEvidenceSourceMatch { field, value: _ } => match field {
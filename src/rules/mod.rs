@@ -1,14 +1,48 @@
+use crate::formats::{read_contents, read_file, to_string, FileFormat, ParseError};
 use std::fs;
+use std::path::Path;
 
-pub mod schema;
+/// The file extension of rules files written in the rules DSL.
+pub const DSL_EXTENSION: &str = "rules";
+
+pub mod dsl;
 pub mod engine;
+pub mod report;
+pub mod schema;
+pub mod testing;
 
-pub fn parse_rules(rules_file: Option<&String>) -> Option<schema::CustomRules> {
-    if let Some(path) = rules_file {
-        let file_contents = fs::read_to_string(path).expect("Could not read rules file.");
-        let rules = serde_json::from_str(&file_contents).expect("Error parsing rules file.");
-        Some(rules)
+/// Reads the custom rules file. Files with the `.rules` extension are parsed as
+/// the rules DSL, otherwise JSON, YAML or TOML is picked by file extension.
+pub fn parse_rules(rules_file: Option<&String>) -> Result<Option<schema::CustomRules>, ParseError> {
+    let Some(path) = rules_file else {
+        return Ok(None);
+    };
+    if Path::new(path).extension().and_then(|e| e.to_str()) == Some(DSL_EXTENSION) {
+        let contents = read_contents(path)?;
+        let rules = dsl::parse_dsl(&contents).map_err(|e| ParseError {
+            path: path.to_owned(),
+            line: Some(e.line),
+            column: Some(e.column),
+            message: e.message,
+        })?;
+        Ok(Some(rules))
     } else {
-        None
+        let rules = read_file(path)?;
+        Ok(Some(rules))
     }
 }
+
+/// Writes the custom rules to a file, as the rules DSL for `.rules` files and
+/// otherwise as JSON, YAML or TOML by file extension.
+pub fn write_rules(
+    rules: &schema::CustomRules,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = if Path::new(path).extension().and_then(|e| e.to_str()) == Some(DSL_EXTENSION) {
+        dsl::format_dsl(rules)
+    } else {
+        to_string(rules, FileFormat::from_path(Path::new(path)))?
+    };
+    fs::write(path, contents)?;
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! Report Module
+//!
+//! Aggregates custom rule applications across a corpus into a per-rule
+//! coverage and impact report.
+
+use crate::models::{BiomarkerScore, CustomRuleApplication};
+use crate::rules::schema::CustomRules;
+use crate::scores::map::ScoreMap;
+use serde::Serialize;
+
+/// The number of sample biomarker IDs kept per rule.
+pub const SAMPLE_SIZE: usize = 5;
+
+/// The coverage and impact of every rule across the scored biomarkers.
+#[derive(Serialize, Debug, Clone)]
+pub struct RuleReport {
+    /// The number of biomarkers the rules were evaluated against.
+    pub biomarkers: usize,
+    /// The per rule coverage, in the order the rules are defined.
+    pub rules: Vec<RuleCoverage>,
+    /// The names of the rules that never matched any biomarker.
+    pub unmatched_rules: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RuleCoverage {
+    pub rule_name: String,
+    pub priority: i32,
+    /// The number of biomarkers the rule matched.
+    pub matched: usize,
+    /// The sum of the rule's score deltas.
+    pub total_effect: f64,
+    pub mean_effect: Option<f64>,
+    pub min_effect: Option<f64>,
+    pub max_effect: Option<f64>,
+    /// Up to `SAMPLE_SIZE` IDs of biomarkers the rule matched.
+    pub sample_biomarkers: Vec<String>,
+}
+
+impl RuleReport {
+    /// Creates an empty report for the rules.
+    pub fn new(rules: &CustomRules) -> Self {
+        RuleReport {
+            biomarkers: 0,
+            rules: rules
+                .rules
+                .iter()
+                .map(|rule| RuleCoverage {
+                    rule_name: rule.name.clone(),
+                    priority: rule.priority,
+                    matched: 0,
+                    total_effect: 0.0,
+                    mean_effect: None,
+                    min_effect: None,
+                    max_effect: None,
+                    sample_biomarkers: Vec::new(),
+                })
+                .collect(),
+            unmatched_rules: rules.rules.iter().map(|rule| rule.name.clone()).collect(),
+        }
+    }
+
+    /// Builds the report from a generated score map.
+    pub fn from_score_map(score_map: &ScoreMap, rules: &CustomRules) -> Self {
+        let mut report = RuleReport::new(rules);
+        for (biomarker_id, score) in score_map.values().flat_map(|scores| scores.iter()) {
+            report.record_score(biomarker_id, score);
+        }
+        report
+    }
+
+    /// Records the rules applied to a scored biomarker.
+    pub fn record_score(&mut self, biomarker_id: &str, score: &BiomarkerScore) {
+        let applied = score.score_info.custom_rules.as_deref().unwrap_or_default();
+        self.record(biomarker_id, applied);
+    }
+
+    /// Records the rule applications returned by `apply_custom_rules` for a biomarker.
+    pub fn record(&mut self, biomarker_id: &str, applied: &[CustomRuleApplication]) {
+        self.biomarkers += 1;
+        if applied.is_empty() {
+            return;
+        }
+        for application in applied {
+            let Some(coverage) = self.rules.iter_mut().find(|c| {
+                c.rule_name == application.rule_name && c.priority == application.priority
+            }) else {
+                continue;
+            };
+            let effect = application.effect;
+            coverage.matched += 1;
+            coverage.total_effect += effect;
+            coverage.mean_effect = Some(coverage.total_effect / coverage.matched as f64);
+            coverage.min_effect = Some(coverage.min_effect.map_or(effect, |m| m.min(effect)));
+            coverage.max_effect = Some(coverage.max_effect.map_or(effect, |m| m.max(effect)));
+            if coverage.sample_biomarkers.len() < SAMPLE_SIZE {
+                coverage.sample_biomarkers.push(biomarker_id.to_owned());
+            }
+        }
+        self.unmatched_rules = self
+            .rules
+            .iter()
+            .filter(|c| c.matched == 0)
+            .map(|c| c.rule_name.clone())
+            .collect();
+    }
+}
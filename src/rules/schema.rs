@@ -2,17 +2,21 @@
 //!
 //! This module sets the schema for the custom rules format and engine parsing.
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 /// The top level custom rule structure.
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct CustomRules {
     /// The list of rules.
     pub rules: Vec<Rule>,
+    /// Optional test fixtures asserting the intended behaviour of the rules.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tests: Vec<RuleTest>,
 }
 
 /// The schema for a single rule.
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Rule {
     /// The rule name, just a concise and descriptive string.
     pub name: String,
@@ -24,8 +28,30 @@ pub struct Rule {
     pub priority: i32,
 }
 
+/// A test fixture for the rules in the file.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RuleTest {
+    /// The test name.
+    pub name: String,
+    /// The input biomarker snippet, missing top level fields default to empty values.
+    pub biomarker: Value,
+    /// Only test the rule with this name, otherwise all the rules are applied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    /// The score the rules are applied to, defaults to the calculated biomarker score
+    /// (clamped if the scores are clamped before the rules).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_score: Option<f64>,
+    /// Whether the rule (or any of the rules) is expected to match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_match: Option<bool>,
+    /// The expected score after the rule(s) are applied, rounded like the final score.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_score: Option<f64>,
+}
+
 /// The condition for the rule to be applied.
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 #[serde(tag = "type")]
 pub enum Condition {
     NonPubmedEvidenceSourceMatch { field: Field, value: String },
@@ -39,8 +65,53 @@ pub enum Condition {
     Or { conditions: Vec<Condition> },
 }
 
+impl Condition {
+    /// Returns the condition type name as used in the rules file.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Condition::NonPubmedEvidenceSourceMatch { .. } => "NonPubmedEvidenceSourceMatch",
+            Condition::FieldEquals { .. } => "FieldEquals",
+            Condition::FieldAllContains { .. } => "FieldAllContains",
+            Condition::FieldSomeContains { .. } => "FieldSomeContains",
+            Condition::FieldLenGreaterThan { .. } => "FieldLenGreaterThan",
+            Condition::FieldLenLessThan { .. } => "FieldLenLessThan",
+            Condition::FieldLenEqual { .. } => "FieldLenEqual",
+            Condition::And { .. } => "And",
+            Condition::Or { .. } => "Or",
+        }
+    }
+
+    /// Returns the field the condition is checked against, `None` for `And`/`Or`.
+    pub fn field(&self) -> Option<&Field> {
+        match self {
+            Condition::NonPubmedEvidenceSourceMatch { field, .. }
+            | Condition::FieldEquals { field, .. }
+            | Condition::FieldAllContains { field, .. }
+            | Condition::FieldSomeContains { field, .. }
+            | Condition::FieldLenGreaterThan { field, .. }
+            | Condition::FieldLenLessThan { field, .. }
+            | Condition::FieldLenEqual { field, .. } => Some(field),
+            Condition::And { .. } | Condition::Or { .. } => None,
+        }
+    }
+
+    /// Returns the condition value as JSON, `None` for `And`/`Or`.
+    pub fn value(&self) -> Option<Value> {
+        match self {
+            Condition::NonPubmedEvidenceSourceMatch { value, .. }
+            | Condition::FieldEquals { value, .. }
+            | Condition::FieldAllContains { value, .. }
+            | Condition::FieldSomeContains { value, .. } => Some(json!(value)),
+            Condition::FieldLenGreaterThan { value, .. }
+            | Condition::FieldLenLessThan { value, .. }
+            | Condition::FieldLenEqual { value, .. } => Some(json!(value)),
+            Condition::And { .. } | Condition::Or { .. } => None,
+        }
+    }
+}
+
 /// The fields that custom rules can be applied to.
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum Field {
     BiomarkerID,
     ComponentEvidenceSourceDatabase,
@@ -65,7 +136,7 @@ impl Field {
 }
 
 /// The action to take when a condition is applied.
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 #[serde(tag = "type", content = "value")]
 pub enum Action {
     /// Hardcode the biomarker score.
@@ -79,3 +150,27 @@ pub enum Action {
     /// Divide a value from the biomarker score.
     DivideScore(f64),
 }
+
+impl Action {
+    /// Returns the action type name as used in the rules file.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Action::SetScore(_) => "SetScore",
+            Action::AddToScore(_) => "AddToScore",
+            Action::MultiplyScore(_) => "MultiplyScore",
+            Action::SubtractScore(_) => "SubtractScore",
+            Action::DivideScore(_) => "DivideScore",
+        }
+    }
+
+    /// Returns the action value.
+    pub fn value(&self) -> f64 {
+        match self {
+            Action::SetScore(value)
+            | Action::AddToScore(value)
+            | Action::MultiplyScore(value)
+            | Action::SubtractScore(value)
+            | Action::DivideScore(value) => *value,
+        }
+    }
+}
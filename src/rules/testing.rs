@@ -0,0 +1,115 @@
+//! Testing Module
+//!
+//! Runs the test fixtures embedded in a custom rules file so rule files can be
+//! checked before a data release.
+
+use crate::models::{to_decimal, to_f64};
+use crate::prelude::*;
+use crate::rules::engine::{apply_action, apply_custom_rules, evaluate_condition};
+use crate::rules::schema::RuleTest;
+use crate::scores::calculate::{clamp_score, weighted_score};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// The tolerance used when comparing the expected and actual scores.
+const SCORE_TOLERANCE: f64 = 1e-9;
+
+/// The result of a single rule test.
+#[derive(Serialize, Debug, Clone)]
+pub struct RuleTestResult {
+    pub name: String,
+    /// The failed expectations, empty if the test passed.
+    pub failures: Vec<String>,
+}
+
+impl RuleTestResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Runs every test fixture in the rules file.
+pub fn run_rule_tests(rules: &CustomRules, weights: &Weights) -> Vec<RuleTestResult> {
+    rules
+        .tests
+        .iter()
+        .map(|test| RuleTestResult {
+            name: test.name.clone(),
+            failures: run_rule_test(rules, test, weights),
+        })
+        .collect()
+}
+
+fn run_rule_test(rules: &CustomRules, test: &RuleTest, weights: &Weights) -> Vec<String> {
+    let biomarker = match build_biomarker(&test.biomarker) {
+        Ok(biomarker) => biomarker,
+        Err(e) => return vec![format!("invalid biomarker snippet: {}", e)],
+    };
+    let stage = weights.clamp.unwrap_or_default();
+    let base_score = match test.base_score {
+        Some(base_score) => to_decimal(base_score),
+        None if stage.before_rules() => {
+            clamp_score(weighted_score(&biomarker, weights, None).0, weights)
+        }
+        None => weighted_score(&biomarker, weights, None).0,
+    };
+
+    let (matched, score) = match &test.rule {
+        Some(rule_name) => {
+            let Some(rule) = rules.rules.iter().find(|r| &r.name == rule_name) else {
+                return vec![format!("no rule named `{}`", rule_name)];
+            };
+            if evaluate_condition(&biomarker, &rule.condition) {
+                (true, apply_action(base_score, &rule.action).0)
+            } else {
+                (false, base_score)
+            }
+        }
+        None => {
+            let (score, applied_rules) = apply_custom_rules(&biomarker, rules, base_score);
+            (!applied_rules.is_empty(), score)
+        }
+    };
+    let score = if stage.after_rules() {
+        clamp_score(score, weights)
+    } else {
+        score
+    };
+    let score = to_f64(weights.round(score));
+
+    let mut failures = Vec::new();
+    if let Some(expect_match) = test.expect_match {
+        if expect_match != matched {
+            failures.push(format!(
+                "expected match to be {}, got {}",
+                expect_match, matched
+            ));
+        }
+    }
+    if let Some(expect_score) = test.expect_score {
+        if (expect_score - score).abs() > SCORE_TOLERANCE {
+            failures.push(format!(
+                "expected score to be {}, got {}",
+                expect_score, score
+            ));
+        }
+    }
+    failures
+}
+
+/// Fills the missing top level fields of the snippet with empty values.
+fn build_biomarker(snippet: &Value) -> Result<MinBiomarker, serde_json::Error> {
+    let Some(fields) = snippet.as_object() else {
+        return Err(serde::de::Error::custom("expected an object"));
+    };
+    let mut biomarker = json!({
+        "biomarker_id": "",
+        "biomarker_component": [],
+        "condition": { "id": "" },
+        "evidence_source": [],
+    });
+    for (key, value) in fields {
+        biomarker[key] = value.clone();
+    }
+    serde_json::from_value(biomarker)
+}
@@ -0,0 +1,63 @@
+//! Aggregate Module
+//!
+//! Scores biomarkers whose records are split across several source files
+//! (e.g. one per contributing resource) as a single biomarker.
+
+use crate::inputs::InputFile;
+use crate::prelude::*;
+use crate::CHECKPOINT;
+use std::collections::{BTreeMap, HashMap};
+use tokio::fs;
+
+/// The score map key the aggregated biomarker scores are stored under.
+pub const AGGREGATE_KEY: &str = "aggregate";
+
+/// Groups the biomarkers of all the input files by ID, merging their
+/// components and evidence (deduplicated by database and ID), and calculates
+/// a single score per biomarker. Each score records the keys of the files
+/// that contributed to it in its `provenance`.
+pub async fn generate_aggregate_score_map(
+    inputs: &[InputFile],
+    weights: &Weights,
+    custom_rules: Option<CustomRules>,
+) -> Result<ScoreMap, Box<dyn std::error::Error>> {
+    let mut aggregated: HashMap<String, (MinBiomarker, Vec<String>)> = HashMap::new();
+
+    for (idx, input) in inputs.iter().enumerate() {
+        if idx % CHECKPOINT == 0 {
+            println!("Checkpoint reached at file index: {}", idx + 1);
+        }
+        let contents = fs::read_to_string(&input.path).await?;
+        let biomarkers: Vec<MinBiomarker> = serde_json::from_str(&contents)?;
+        for biomarker in biomarkers {
+            match aggregated.get_mut(&biomarker.biomarker_id) {
+                Some((existing, provenance)) => {
+                    existing.merge(biomarker);
+                    if !provenance.contains(&input.key) {
+                        provenance.push(input.key.clone());
+                    }
+                }
+                None => {
+                    aggregated.insert(
+                        biomarker.biomarker_id.clone(),
+                        (biomarker, vec![input.key.clone()]),
+                    );
+                }
+            }
+        }
+    }
+
+    let scores = aggregated
+        .into_iter()
+        .map(|(biomarker_id, (biomarker, provenance))| {
+            let (score, score_info) = calculate_score(&biomarker, weights, custom_rules.as_ref());
+            let score = BiomarkerScore {
+                provenance: Some(provenance),
+                ..BiomarkerScore::new(score, score_info)
+            };
+            (biomarker_id, score)
+        })
+        .collect();
+
+    Ok(BTreeMap::from([(AGGREGATE_KEY.to_owned(), scores)]))
+}
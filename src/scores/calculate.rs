@@ -1,7 +1,14 @@
 use crate::models::traits::{BiomarkerData, ComponentData, EvidenceData, SpecimenData};
+use crate::models::{to_decimal, to_f64, DataQuality, InvalidLoinc, ScoreClamp};
 use crate::prelude::*;
-use crate::rules::engine::apply_custom_rules;
-use std::collections::HashSet;
+use crate::rules::engine::apply_custom_rules_traced;
+use crate::scores::explain::{EvidenceOutcome, EvidenceStep, ScoreStep, ScoreTrace};
+use crate::scores::loinc::validate_loinc;
+use rust_decimal::Decimal;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// The number of decimal places of the recency frequency.
+const RECENCY_PRECISION: u32 = 4;
 
 pub fn calculate_score<B>(
     biomarker: &B,
@@ -13,25 +20,167 @@ where
     B::Evidence: AsRef<B::Evidence>,
     B::Component: AsRef<B::Component>,
 {
-    let mut score = 0.0;
+    score_biomarker(biomarker, weights, custom_rules, None)
+}
+
+/// Calculates the score, recording every step of the computation in `trace` if provided.
+/// The arithmetic is exact, the score is only rounded once the custom rules are applied.
+pub(crate) fn score_biomarker<B>(
+    biomarker: &B,
+    weights: &Weights,
+    custom_rules: Option<&CustomRules>,
+    mut trace: Option<&mut ScoreTrace>,
+) -> (f64, ScoreInfo)
+where
+    B: BiomarkerData,
+    B::Evidence: AsRef<B::Evidence>,
+    B::Component: AsRef<B::Component>,
+{
+    let (score, contributions, data_quality) =
+        weighted_score(biomarker, weights, trace.as_deref_mut());
+    let stage = weights.clamp.unwrap_or_default();
+
+    let mut before_rules = None;
+    let score = if stage.before_rules() {
+        let clamped = clamp_score(score, weights);
+        before_rules = Some(to_f64(clamped - score));
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.steps.push(ScoreStep {
+                description: clamp_description(weights),
+                running_score: to_f64(clamped),
+            });
+        }
+        clamped
+    } else {
+        score
+    };
+    let base_score = to_f64(weights.round(score));
+    if let Some(trace) = trace.as_deref_mut() {
+        trace.base_score = base_score;
+    }
+
+    let (final_score, applied_rules) = if let Some(rules) = custom_rules {
+        apply_custom_rules_traced(
+            biomarker,
+            rules,
+            score,
+            trace.as_deref_mut().map(|t| &mut t.rules),
+        )
+    } else {
+        (score, Vec::new())
+    };
+
+    let mut after_rules = None;
+    let final_score = if stage.after_rules() {
+        let clamped = clamp_score(final_score, weights);
+        after_rules = Some(to_f64(clamped - final_score));
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.final_steps.push(ScoreStep {
+                description: clamp_description(weights),
+                running_score: to_f64(clamped),
+            });
+        }
+        clamped
+    } else {
+        final_score
+    };
+    let final_score = to_f64(weights.round(final_score));
+    if let Some(trace) = trace {
+        trace.final_score = final_score;
+    }
+
+    let score_info = ScoreInfo {
+        contributions,
+        formula: "sum(w*f)".to_owned(),
+        variables: [
+            ("c".to_string(), "condition".to_string()),
+            ("w".to_string(), "weight".to_string()),
+            ("f".to_string(), "frequency".to_string()),
+        ]
+        .iter()
+        .cloned()
+        .collect(),
+        base_score,
+        clamp: Some(ScoreClamp {
+            min_score: weights.min_score(),
+            max_score: weights.max_score(),
+            stage,
+            before_rules,
+            after_rules,
+        }),
+        custom_rules: if !applied_rules.is_empty() {
+            Some(applied_rules)
+        } else {
+            None
+        },
+        data_quality,
+    };
+
+    (final_score, score_info)
+}
+
+/// Clamps the score to the `min_score`/`max_score` bounds.
+pub(crate) fn clamp_score(mut score: Decimal, weights: &Weights) -> Decimal {
+    if let Some(max_score) = weights.max_score() {
+        score = score.min(to_decimal(max_score));
+    }
+    if let Some(min_score) = weights.min_score() {
+        score = score.max(to_decimal(min_score));
+    }
+    score
+}
+
+fn clamp_description(weights: &Weights) -> String {
+    let bound = |b: Option<f64>| b.map_or("none".to_owned(), |b| b.to_string());
+    format!(
+        "clamp to min {} and max {}",
+        bound(weights.min_score()),
+        bound(weights.max_score())
+    )
+}
+
+/// The unrounded sum of the weighted contributions, and the problems found in the data.
+pub(crate) fn weighted_score<B>(
+    biomarker: &B,
+    weights: &Weights,
+    mut trace: Option<&mut ScoreTrace>,
+) -> (Decimal, Vec<ScoreContribution>, Option<DataQuality>)
+where
+    B: BiomarkerData,
+    B::Evidence: AsRef<B::Evidence>,
+    B::Component: AsRef<B::Component>,
+{
+    let mut score = Decimal::ZERO;
     let mut unique_pmids = HashSet::new();
     let mut unique_sources = HashSet::new();
     let mut contributions = Vec::new();
 
     // Chain the evidence iterators together for reduced redundancy
-    let all_evidence = biomarker.evidence_sources().iter().chain(
-        biomarker
-            .biomarker_components()
-            .iter()
-            .flat_map(|component| component.evidence_source().iter()),
-    );
+    let all_evidence = biomarker
+        .evidence_sources()
+        .iter()
+        .map(|evidence| (None, evidence))
+        .chain(
+            biomarker
+                .biomarker_components()
+                .iter()
+                .enumerate()
+                .flat_map(|(idx, component)| {
+                    component
+                        .evidence_source()
+                        .iter()
+                        .map(move |evidence| (Some(idx), evidence))
+                }),
+        );
 
     let mut first_pmid_count = 0;
     let mut other_pmid_count = 0;
     let mut first_source_count = 0;
     let mut other_source_count = 0;
+    let mut scored_pmids = Vec::new();
+    let mut scored_aspects: HashMap<(bool, String), BTreeSet<String>> = HashMap::new();
 
-    for evidence in all_evidence {
+    for (component, evidence) in all_evidence {
         let is_pubmed = evidence.database().to_lowercase().trim() == "pubmed";
         let unique_set = if is_pubmed {
             &mut unique_pmids
@@ -40,24 +189,56 @@ where
         };
 
         let evidence_id = evidence.id().to_owned();
-        if unique_set.insert(evidence_id) {
+        let outcome = if unique_set.insert(evidence_id) {
             if is_pubmed {
                 if unique_pmids.len() == 1 {
-                    score += weights.first_pmid.unwrap_or(FIRST_PMID) as f64;
+                    score += Decimal::from(weights.first_pmid.unwrap_or(FIRST_PMID));
                     first_pmid_count += 1;
+                    scored_pmids.push(evidence);
+                    EvidenceOutcome::FirstPmid
                 } else if unique_pmids.len() <= weights.pmid_limit.unwrap_or(PMID_LIMIT) {
-                    score += weights.other_pmid.unwrap_or(OTHER_PMID);
+                    score += to_decimal(weights.other_pmid.unwrap_or(OTHER_PMID));
                     other_pmid_count += 1;
-                }
-            } else {
-                if unique_sources.len() == 1 {
-                    score += weights.first_source.unwrap_or(FIRST_SOURCE) as f64;
-                    first_source_count += 1;
+                    scored_pmids.push(evidence);
+                    EvidenceOutcome::OtherPmid
                 } else {
-                    score += weights.other_source.unwrap_or(OTHER_SOURCE);
-                    other_source_count += 1;
+                    EvidenceOutcome::PmidLimit
                 }
+            } else if unique_sources.len() == 1 {
+                score += Decimal::from(weights.first_source.unwrap_or(FIRST_SOURCE));
+                first_source_count += 1;
+                EvidenceOutcome::FirstSource
+            } else {
+                score += to_decimal(weights.other_source.unwrap_or(OTHER_SOURCE));
+                other_source_count += 1;
+                EvidenceOutcome::OtherSource
             }
+        } else {
+            EvidenceOutcome::Duplicate
+        };
+
+        // Collect the aspects supported by the scored evidence, including the
+        // tags of its duplicates
+        let key = (is_pubmed, evidence.id().to_owned());
+        let scored = !matches!(
+            outcome,
+            EvidenceOutcome::Duplicate | EvidenceOutcome::PmidLimit
+        );
+        if scored || scored_aspects.contains_key(&key) {
+            scored_aspects
+                .entry(key)
+                .or_default()
+                .extend(evidence.tags().into_iter().map(tag_aspect));
+        }
+
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.evidence.push(EvidenceStep {
+                component,
+                database: evidence.database().to_owned(),
+                id: evidence.id().to_owned(),
+                outcome,
+                running_score: to_f64(score),
+            });
         }
     }
 
@@ -82,6 +263,60 @@ where
         f: other_source_count as f64,
     });
 
+    // Weight the scored PubMed evidence by the age of the publication
+    if let Some(recency) = &weights.recency {
+        let years: Vec<Option<i32>> = scored_pmids
+            .iter()
+            .map(|evidence| recency.year(evidence.year(), evidence.id()))
+            .collect();
+        let frequency = years
+            .iter()
+            .map(|&year| to_decimal(recency.factor(year)))
+            .sum::<Decimal>()
+            .round_dp(RECENCY_PRECISION);
+        let weight = recency.weight.unwrap_or(RECENCY_WEIGHT);
+        score += to_decimal(weight) * frequency;
+        contributions.push(ScoreContribution {
+            c: "recency".to_string(),
+            w: weight,
+            f: to_f64(frequency),
+        });
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.steps.push(ScoreStep {
+                description: format!(
+                    "recency: {} of {} scored pubmed evidence with a known year",
+                    years.iter().flatten().count(),
+                    years.len()
+                ),
+                running_score: to_f64(score),
+            });
+        }
+    }
+
+    // Weight the scored evidence by the aspects it supports
+    if let Some(tag_weights) = &weights.tag_weights {
+        let mut counts = Vec::new();
+        for (aspect, &weight) in tag_weights {
+            let count = scored_aspects
+                .values()
+                .filter(|aspects| aspects.contains(&aspect.to_lowercase()))
+                .count();
+            score += to_decimal(weight) * Decimal::from(count);
+            contributions.push(ScoreContribution {
+                c: format!("tag_{}", aspect),
+                w: weight,
+                f: count as f64,
+            });
+            counts.push(format!("{} {}", aspect, count));
+        }
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.steps.push(ScoreStep {
+                description: format!("tags: {}", counts.join(", ")),
+                running_score: to_f64(score),
+            });
+        }
+    }
+
     // Check for generic condition penalty
     let mut generic_condition_count = 0;
     if weights
@@ -90,9 +325,11 @@ where
         .unwrap_or(GENERIC_CONDITIONS.iter().map(|&s| s.to_owned()).collect())
         .contains(biomarker.condition_id())
     {
-        score += weights
-            .generic_condition_pen
-            .unwrap_or(GENERIC_CONDITION_PEN) as f64;
+        score += Decimal::from(
+            weights
+                .generic_condition_pen
+                .unwrap_or(GENERIC_CONDITION_PEN),
+        );
         generic_condition_count += 1;
     }
     contributions.push(ScoreContribution {
@@ -102,53 +339,158 @@ where
             .unwrap_or(GENERIC_CONDITION_PEN) as f64,
         f: generic_condition_count as f64,
     });
+    if let Some(trace) = trace.as_deref_mut() {
+        trace.steps.push(ScoreStep {
+            description: format!(
+                "generic condition penalty: condition `{}` {}",
+                biomarker.condition_id(),
+                if generic_condition_count > 0 {
+                    "is generic"
+                } else {
+                    "is not generic"
+                }
+            ),
+            running_score: to_f64(score),
+        });
+    }
 
-    // Handle LOINC scoring criteria
-    let mut loinc_count = 0;
-    if biomarker.biomarker_components().iter().any(|component| {
-        component
-            .as_ref()
-            .specimen()
-            .iter()
-            .any(|specimen| !specimen.loinc_code().is_empty())
-    }) {
-        score += weights.loinc.unwrap_or(LOINC) as f64;
-        loinc_count += 1;
+    // Handle LOINC scoring criteria, only valid codes are scored
+    let mut valid_codes = BTreeSet::new();
+    let mut invalid_codes = Vec::new();
+    for (idx, component) in biomarker.biomarker_components().iter().enumerate() {
+        for specimen in component.as_ref().specimen() {
+            let code = specimen.loinc_code().trim();
+            if code.is_empty() {
+                continue;
+            }
+            match validate_loinc(code, weights.loinc_codes.as_ref()) {
+                Ok(()) => {
+                    valid_codes.insert(code);
+                }
+                Err(issue) => invalid_codes.push(InvalidLoinc {
+                    component: idx,
+                    specimen_id: specimen.id().to_owned(),
+                    loinc_code: specimen.loinc_code().to_owned(),
+                    issue,
+                }),
+            }
+        }
     }
+    let loinc_count = match weights.loinc_limit {
+        Some(limit) => valid_codes.len().min(limit),
+        None => valid_codes.len().min(1),
+    };
+    score += Decimal::from(weights.loinc.unwrap_or(LOINC)) * Decimal::from(loinc_count);
     contributions.push(ScoreContribution {
         c: "loinc".to_string(),
         w: weights.loinc.unwrap_or(LOINC) as f64,
         f: loinc_count as f64,
     });
+    if let Some(trace) = trace.as_deref_mut() {
+        trace.steps.push(ScoreStep {
+            description: format!(
+                "loinc: {} valid codes, {} counted, {} invalid codes",
+                valid_codes.len(),
+                loinc_count,
+                invalid_codes.len()
+            ),
+            running_score: to_f64(score),
+        });
+    }
 
-    // Round negative score back up to zero
-    score = score.max(0.0);
+    // Handle the specimen diversity scoring criteria
+    if let Some(weight) = weights.specimen_diversity {
+        let specimens: HashSet<String> = biomarker
+            .biomarker_components()
+            .iter()
+            .flat_map(|component| component.as_ref().specimen())
+            .map(|specimen| specimen.id().trim().to_lowercase())
+            .filter(|id| !id.is_empty())
+            .collect();
+        let count = specimens
+            .len()
+            .min(weights.specimen_limit.unwrap_or(usize::MAX));
+        score += to_decimal(weight) * Decimal::from(count);
+        contributions.push(ScoreContribution {
+            c: "specimen_diversity".to_string(),
+            w: weight,
+            f: count as f64,
+        });
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.steps.push(ScoreStep {
+                description: format!(
+                    "specimen diversity: {} distinct specimens, {} counted",
+                    specimens.len(),
+                    count
+                ),
+                running_score: to_f64(score),
+            });
+        }
+    }
 
-    let score = (score * 100.0).round() / 100.0; // Round to 2 decimal places
+    // Handle the panel biomarker scoring criteria
+    if let Some(weight) = weights.panel_component {
+        let count = biomarker.biomarker_components().len().saturating_sub(1);
+        score += to_decimal(weight) * Decimal::from(count);
+        contributions.push(ScoreContribution {
+            c: "panel_component".to_string(),
+            w: weight,
+            f: count as f64,
+        });
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.steps.push(ScoreStep {
+                description: format!(
+                    "panel: {} components",
+                    biomarker.biomarker_components().len()
+                ),
+                running_score: to_f64(score),
+            });
+        }
+    }
 
-    let (final_score, applied_rules) = if let Some(rules) = custom_rules {
-        apply_custom_rules(biomarker, rules, score)
+    // Handle the entity type scoring criteria
+    if let Some(entity_type_weights) = &weights.entity_type_weights {
+        let entity_types: HashSet<String> = biomarker
+            .biomarker_components()
+            .iter()
+            .filter_map(|component| component.as_ref().entity_type())
+            .map(|entity_type| entity_type.trim().to_lowercase())
+            .collect();
+        for (entity_type, &weight) in entity_type_weights {
+            let count = entity_types.contains(&entity_type.to_lowercase()) as usize;
+            score += to_decimal(weight) * Decimal::from(count);
+            contributions.push(ScoreContribution {
+                c: format!("entity_type_{}", entity_type),
+                w: weight,
+                f: count as f64,
+            });
+        }
+        if let Some(trace) = trace {
+            let mut entity_types: Vec<String> = entity_types.into_iter().collect();
+            entity_types.sort();
+            trace.steps.push(ScoreStep {
+                description: if entity_types.is_empty() {
+                    "entity types: none".to_owned()
+                } else {
+                    format!("entity types: {}", entity_types.join(", "))
+                },
+                running_score: to_f64(score),
+            });
+        }
+    }
+
+    let data_quality = if invalid_codes.is_empty() {
+        None
     } else {
-        (score, Vec::new())
+        Some(DataQuality {
+            invalid_loinc_codes: invalid_codes,
+        })
     };
 
-    let score_info = ScoreInfo {
-        contributions,
-        formula: "sum(w*f)".to_owned(),
-        variables: [
-            ("c".to_string(), "condition".to_string()),
-            ("w".to_string(), "weight".to_string()),
-            ("f".to_string(), "frequency".to_string()),
-        ]
-        .iter()
-        .cloned()
-        .collect(),
-        custom_rules: if !applied_rules.is_empty() {
-            Some(applied_rules)
-        } else {
-            None
-        },
-    };
+    (score, contributions, data_quality)
+}
 
-    (final_score, score_info)
+/// The aspect an evidence tag supports, the lowercase tag up to the first `:`.
+fn tag_aspect(tag: &str) -> String {
+    tag.split(':').next().unwrap_or(tag).trim().to_lowercase()
 }
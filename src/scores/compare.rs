@@ -0,0 +1,441 @@
+//! Compare Module
+//!
+//! What-if comparison of several named weight/rule configurations, reading
+//! the input files once and scoring every biomarker per configuration.
+
+use crate::config::FileOrInline;
+use crate::formats::read_file;
+use crate::inputs::InputFile;
+use crate::prelude::*;
+use crate::scores::diff::Ranks;
+use crate::scores::duplicates::{
+    check_cross_file_duplicates, cross_file_duplicates, dedupe_biomarkers,
+};
+use crate::CHECKPOINT;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use tokio::fs;
+
+/// A named weight/rule configuration to score the inputs with.
+pub struct Scenario {
+    pub name: String,
+    pub weights: Weights,
+    pub rules: Option<CustomRules>,
+}
+
+/// The scenarios file, a list so the order of the scenarios is kept.
+#[derive(Deserialize)]
+struct ScenariosFile {
+    scenarios: Vec<ScenarioEntry>,
+}
+
+#[derive(Deserialize)]
+struct ScenarioEntry {
+    name: String,
+    /// The weight overrides, either inline or a path to an overrides file.
+    weights: Option<FileOrInline<Weights>>,
+    /// The custom rules, either inline or a path to a rules file.
+    rules: Option<FileOrInline<CustomRules>>,
+}
+
+/// The scores and ranks of every biomarker per scenario.
+#[derive(Serialize, Debug, Clone)]
+pub struct Comparison {
+    pub scenarios: Vec<String>,
+    pub biomarkers: Vec<ComparedBiomarker>,
+    /// How much every pair of scenarios agrees on the ranking.
+    pub summary: Vec<RankAgreement>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ComparedBiomarker {
+    pub file: String,
+    pub biomarker_id: String,
+    /// The score per scenario, in scenario order.
+    pub scores: Vec<f64>,
+    /// The rank per scenario (highest score first, ties share a rank), in scenario order.
+    pub ranks: Vec<usize>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RankAgreement {
+    pub first: String,
+    pub second: String,
+    /// Kendall's tau-b between the two rankings, `None` if either ranks every biomarker the same.
+    pub kendall_tau: Option<f64>,
+    /// The number of biomarkers with a different score.
+    pub changed_scores: usize,
+    pub mean_rank_change: f64,
+    pub max_rank_change: usize,
+}
+
+/// Reads the scenarios file (JSON, YAML or TOML by file extension). Relative
+/// paths are resolved against the directory of the scenarios file.
+pub fn load_scenarios(path: &str) -> Result<Vec<Scenario>, Box<dyn std::error::Error>> {
+    let file: ScenariosFile = read_file(path)?;
+    let base = Path::new(path).parent().unwrap_or(Path::new(""));
+    let mut names = HashSet::new();
+    let mut scenarios = Vec::with_capacity(file.scenarios.len());
+
+    for mut entry in file.scenarios {
+        if !names.insert(entry.name.clone()) {
+            return Err(format!("Duplicate scenario name `{}` in {}", entry.name, path).into());
+        }
+        let weights = match entry.weights.as_mut() {
+            Some(weights) => {
+                weights.resolve_paths(base);
+                weights.weights()?
+            }
+            None => Weights::with_defaults(None),
+        };
+        let rules = match entry.rules {
+            Some(mut rules) => {
+                rules.resolve_path(base);
+                rules.into_rules()?
+            }
+            None => None,
+        };
+        scenarios.push(Scenario {
+            name: entry.name,
+            weights,
+            rules,
+        });
+    }
+
+    if scenarios.is_empty() {
+        return Err(format!("No scenarios in {}", path).into());
+    }
+    Ok(scenarios)
+}
+
+/// Reads every input file once and scores its biomarkers with every scenario.
+/// Biomarkers sharing an ID are handled by the duplicate policy, as in map mode.
+pub async fn compare_scenarios(
+    inputs: &[InputFile],
+    scenarios: &[Scenario],
+    duplicate_policy: DuplicatePolicy,
+) -> Result<Comparison, Box<dyn std::error::Error>> {
+    let mut biomarkers = Vec::new();
+    for (idx, input) in inputs.iter().enumerate() {
+        if idx % CHECKPOINT == 0 {
+            println!("Checkpoint reached at file index: {}", idx + 1);
+        }
+        let contents = fs::read_to_string(&input.path).await?;
+        let records: Vec<MinBiomarker> = serde_json::from_str(&contents)?;
+        let records = dedupe_biomarkers(
+            records,
+            duplicate_policy,
+            &input.key,
+            &mut DuplicateReport::default(),
+        )?;
+        for biomarker in records {
+            let scores = scenarios
+                .iter()
+                .map(|scenario| {
+                    calculate_score(&biomarker, &scenario.weights, scenario.rules.as_ref()).0
+                })
+                .collect();
+            biomarkers.push(ComparedBiomarker {
+                file: input.key.clone(),
+                biomarker_id: biomarker.biomarker_id,
+                scores,
+                ranks: Vec::with_capacity(scenarios.len()),
+            });
+        }
+    }
+    let duplicates = cross_file_duplicates(
+        biomarkers
+            .iter()
+            .map(|b| (b.file.as_str(), b.biomarker_id.as_str())),
+    );
+    check_cross_file_duplicates(duplicate_policy, &duplicates)?;
+    biomarkers.sort_by(|a, b| (&a.file, &a.biomarker_id).cmp(&(&b.file, &b.biomarker_id)));
+
+    for idx in 0..scenarios.len() {
+        let ranks = Ranks::from_scores(biomarkers.iter().map(|b| b.scores[idx]).collect());
+        for biomarker in biomarkers.iter_mut() {
+            let rank = ranks.rank(biomarker.scores[idx]);
+            biomarker.ranks.push(rank);
+        }
+    }
+
+    let mut summary = Vec::new();
+    for first in 0..scenarios.len() {
+        for second in first + 1..scenarios.len() {
+            summary.push(rank_agreement(&biomarkers, scenarios, first, second));
+        }
+    }
+
+    Ok(Comparison {
+        scenarios: scenarios.iter().map(|s| s.name.clone()).collect(),
+        biomarkers,
+        summary,
+    })
+}
+
+/// Writes the comparison as JSON, or as a CSV/TSV table with a score and a
+/// rank column per scenario if the file has a `.csv`/`.tsv` extension.
+pub async fn write_comparison(
+    comparison: &Comparison,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let delimiter = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("csv") => b',',
+        Some("tsv") => b'\t',
+        _ => {
+            fs::write(path, serde_json::to_string_pretty(comparison)?).await?;
+            return Ok(());
+        }
+    };
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+    let mut header = vec!["file".to_owned(), "biomarker_id".to_owned()];
+    for name in &comparison.scenarios {
+        header.push(format!("{}_score", name));
+        header.push(format!("{}_rank", name));
+    }
+    writer.write_record(&header)?;
+    for biomarker in &comparison.biomarkers {
+        let mut record = vec![biomarker.file.clone(), biomarker.biomarker_id.clone()];
+        for (score, rank) in biomarker.scores.iter().zip(&biomarker.ranks) {
+            record.push(score.to_string());
+            record.push(rank.to_string());
+        }
+        writer.write_record(&record)?;
+    }
+    let contents = writer.into_inner().map_err(|e| e.into_error())?;
+    fs::write(path, contents).await?;
+    Ok(())
+}
+
+fn rank_agreement(
+    biomarkers: &[ComparedBiomarker],
+    scenarios: &[Scenario],
+    first: usize,
+    second: usize,
+) -> RankAgreement {
+    let pairs: Vec<(f64, f64)> = biomarkers
+        .iter()
+        .map(|b| (b.scores[first], b.scores[second]))
+        .collect();
+    let rank_changes: Vec<usize> = biomarkers
+        .iter()
+        .map(|b| b.ranks[first].abs_diff(b.ranks[second]))
+        .collect();
+
+    RankAgreement {
+        first: scenarios[first].name.clone(),
+        second: scenarios[second].name.clone(),
+        kendall_tau: kendall_tau(pairs.clone()),
+        changed_scores: pairs.iter().filter(|(a, b)| a != b).count(),
+        mean_rank_change: if rank_changes.is_empty() {
+            0.0
+        } else {
+            rank_changes.iter().sum::<usize>() as f64 / rank_changes.len() as f64
+        },
+        max_rank_change: rank_changes.into_iter().max().unwrap_or(0),
+    }
+}
+
+/// Kendall's tau-b of the paired scores, accounting for ties, computed with
+/// Knight's O(n log n) algorithm.
+fn kendall_tau(mut pairs: Vec<(f64, f64)>) -> Option<f64> {
+    let n = pairs.len() as u64;
+    if n < 2 {
+        return None;
+    }
+    let total = n * (n - 1) / 2;
+
+    // Sort by the first score, then the second, and count the ties
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    let first_ties = count_ties(&pairs, |a, b| a.0 == b.0);
+    let joint_ties = count_ties(&pairs, |a, b| a == b);
+
+    // Sorting by the second score counts the discordant pairs as swaps
+    let mut second: Vec<f64> = pairs.iter().map(|p| p.1).collect();
+    let swaps = merge_sort_swaps(&mut second);
+    let second_ties = count_ties(&second, |a, b| a == b);
+
+    let denominator = ((total - first_ties) as f64 * (total - second_ties) as f64).sqrt();
+    if denominator == 0.0 {
+        return None;
+    }
+    let concordant_minus_discordant = total as f64 - first_ties as f64 - second_ties as f64
+        + joint_ties as f64
+        - 2.0 * swaps as f64;
+    Some(concordant_minus_discordant / denominator)
+}
+
+/// The number of tied pairs in the sorted values.
+fn count_ties<T>(sorted: &[T], equal: impl Fn(&T, &T) -> bool) -> u64 {
+    let mut ties = 0;
+    let mut run = 1u64;
+    for window in sorted.windows(2) {
+        if equal(&window[0], &window[1]) {
+            run += 1;
+        } else {
+            ties += run * (run - 1) / 2;
+            run = 1;
+        }
+    }
+    ties + run * (run - 1) / 2
+}
+
+/// Sorts the values ascending, returning the number of swaps a bubble sort would make.
+fn merge_sort_swaps(values: &mut [f64]) -> u64 {
+    if values.len() < 2 {
+        return 0;
+    }
+    let mid = values.len() / 2;
+    let mut swaps = merge_sort_swaps(&mut values[..mid]) + merge_sort_swaps(&mut values[mid..]);
+
+    let mut merged = Vec::with_capacity(values.len());
+    let (mut i, mut j) = (0, mid);
+    while i < mid && j < values.len() {
+        if values[j].total_cmp(&values[i]) == Ordering::Less {
+            swaps += (mid - i) as u64;
+            merged.push(values[j]);
+            j += 1;
+        } else {
+            merged.push(values[i]);
+            i += 1;
+        }
+    }
+    merged.extend_from_slice(&values[i..mid]);
+    merged.extend_from_slice(&values[j..]);
+    values.copy_from_slice(&merged);
+    swaps
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Compared {} biomarkers across {} scenarios",
+            self.biomarkers.len(),
+            self.scenarios.len()
+        )?;
+        for agreement in &self.summary {
+            let tau = match agreement.kendall_tau {
+                Some(tau) => format!("{:.4}", tau),
+                None => "undefined".to_owned(),
+            };
+            write!(
+                f,
+                "\n  {} vs {}: kendall tau {}, {} scores changed, mean rank change {:.2}, max rank change {}",
+                agreement.first,
+                agreement.second,
+                tau,
+                agreement.changed_scores,
+                agreement.mean_rank_change,
+                agreement.max_rank_change
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Kendall's tau-b computed pair by pair.
+    fn brute_force_tau(pairs: &[(f64, f64)]) -> Option<f64> {
+        let (mut concordant, mut discordant, mut first_ties, mut second_ties) = (0, 0, 0, 0);
+        for i in 0..pairs.len() {
+            for j in i + 1..pairs.len() {
+                let first = pairs[i].0.total_cmp(&pairs[j].0);
+                let second = pairs[i].1.total_cmp(&pairs[j].1);
+                if first == Ordering::Equal {
+                    first_ties += 1;
+                }
+                if second == Ordering::Equal {
+                    second_ties += 1;
+                }
+                if first != Ordering::Equal && second != Ordering::Equal {
+                    if first == second {
+                        concordant += 1;
+                    } else {
+                        discordant += 1;
+                    }
+                }
+            }
+        }
+        let total = pairs.len() * pairs.len().saturating_sub(1) / 2;
+        let denominator = (((total - first_ties) * (total - second_ties)) as f64).sqrt();
+        if denominator == 0.0 {
+            return None;
+        }
+        Some((concordant as f64 - discordant as f64) / denominator)
+    }
+
+    #[test]
+    fn kendall_tau_perfect_agreement() {
+        let pairs = vec![(1.0, 10.0), (2.0, 20.0), (3.0, 30.0), (4.0, 40.0)];
+        assert_eq!(kendall_tau(pairs), Some(1.0));
+        let pairs = vec![(1.0, 40.0), (2.0, 30.0), (3.0, 20.0), (4.0, 10.0)];
+        assert_eq!(kendall_tau(pairs), Some(-1.0));
+    }
+
+    #[test]
+    fn kendall_tau_undefined() {
+        assert_eq!(kendall_tau(Vec::new()), None);
+        assert_eq!(kendall_tau(vec![(1.0, 2.0)]), None);
+        // Every biomarker ranked the same by one of the scenarios
+        assert_eq!(kendall_tau(vec![(1.0, 5.0), (2.0, 5.0), (3.0, 5.0)]), None);
+    }
+
+    #[test]
+    fn kendall_tau_matches_brute_force_with_ties() {
+        let cases: Vec<Vec<(f64, f64)>> = vec![
+            vec![(1.0, 2.0), (2.0, 1.0), (3.0, 3.0)],
+            vec![(1.0, 1.0), (1.0, 2.0), (2.0, 2.0), (3.0, 1.0), (3.0, 3.0)],
+            vec![
+                (0.0, 0.0),
+                (0.0, 0.0),
+                (1.0, 1.0),
+                (2.0, 0.0),
+                (2.0, 5.0),
+                (4.0, 4.0),
+            ],
+            (0..40)
+                .map(|i| (((i * 7) % 11) as f64, ((i * 5) % 13) as f64 / 2.0))
+                .collect(),
+        ];
+        for pairs in cases {
+            let expected = brute_force_tau(&pairs).unwrap();
+            let actual = kendall_tau(pairs.clone()).unwrap();
+            assert!(
+                (expected - actual).abs() < 1e-12,
+                "{:?}: expected {}, got {}",
+                pairs,
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn merge_sort_swaps_counts_inversions() {
+        let mut values = vec![3.0, 1.0, 2.0];
+        assert_eq!(merge_sort_swaps(&mut values), 2);
+        assert_eq!(values, vec![1.0, 2.0, 3.0]);
+
+        let mut values = vec![5.0, 4.0, 3.0, 2.0, 1.0];
+        assert_eq!(merge_sort_swaps(&mut values), 10);
+        assert_eq!(values, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+
+        // Equal values are not swapped
+        let mut values = vec![2.0, 1.0, 2.0, 1.0];
+        assert_eq!(merge_sort_swaps(&mut values), 3);
+        assert_eq!(values, vec![1.0, 1.0, 2.0, 2.0]);
+
+        let mut values: Vec<f64> = Vec::new();
+        assert_eq!(merge_sort_swaps(&mut values), 0);
+    }
+}
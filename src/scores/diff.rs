@@ -0,0 +1,511 @@
+//! Diff Module
+//!
+//! Compares the scores of two runs or releases, e.g. after changing the
+//! weights or the custom rules, and attributes every score change to the
+//! contributions and rules that caused it.
+
+use crate::inputs::{key_inputs, resolve_inputs, KeyRoot};
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use tokio::fs;
+
+/// The differences between two sets of scores.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ScoreDiff {
+    /// Biomarkers only found in the new scores.
+    pub added: Vec<DiffEntry>,
+    /// Biomarkers only found in the old scores.
+    pub removed: Vec<DiffEntry>,
+    /// Biomarkers whose score moved by more than the threshold, largest change first.
+    pub changed: Vec<ScoreChange>,
+    /// Biomarkers whose score didn't move by more than the threshold but whose
+    /// rank changed, largest rank change first.
+    pub rank_changed: Vec<RankChange>,
+    /// The number of biomarkers found in both whose score didn't move by more
+    /// than the threshold and whose rank didn't change.
+    pub unchanged: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DiffEntry {
+    pub file: String,
+    pub biomarker_id: String,
+    pub score: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ScoreChange {
+    pub file: String,
+    pub biomarker_id: String,
+    pub old_score: f64,
+    pub new_score: f64,
+    pub delta: f64,
+    /// The rank among all the old scores, highest score first (ties share a rank).
+    pub old_rank: usize,
+    /// The rank among all the new scores, highest score first (ties share a rank).
+    pub new_rank: usize,
+    /// The contributions whose weighted value (`w*f`) changed.
+    pub contributions: Vec<ContributionChange>,
+    /// The custom rules whose effect changed, including rules only applied in one of the runs.
+    pub rules: Vec<RuleChange>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RankChange {
+    pub file: String,
+    pub biomarker_id: String,
+    pub old_score: f64,
+    pub new_score: f64,
+    pub old_rank: usize,
+    pub new_rank: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ContributionChange {
+    pub condition: String,
+    pub old_weight: Option<f64>,
+    pub new_weight: Option<f64>,
+    pub old_frequency: Option<f64>,
+    pub new_frequency: Option<f64>,
+    /// The change of the weighted value (`w*f`).
+    pub delta: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RuleChange {
+    pub rule_name: String,
+    /// The effect of the rule in the old run, `None` if it wasn't applied.
+    pub old_effect: Option<f64>,
+    /// The effect of the rule in the new run, `None` if it wasn't applied.
+    pub new_effect: Option<f64>,
+}
+
+/// The scores to compare, by file key and biomarker ID.
+pub type DiffScoreMap = BTreeMap<String, BTreeMap<String, DiffScore>>;
+
+/// The parts of a biomarker score the diff compares. Everything else is
+/// ignored, so the score maps of older releases (e.g. without rule priorities,
+/// or with the rule action as a string) can be compared too.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DiffScore {
+    pub score: f64,
+    #[serde(default)]
+    pub score_info: DiffScoreInfo,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct DiffScoreInfo {
+    #[serde(default)]
+    pub contributions: Vec<ScoreContribution>,
+    #[serde(default)]
+    pub custom_rules: Option<Vec<DiffRule>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DiffRule {
+    pub rule_name: String,
+    pub effect: f64,
+}
+
+/// A biomarker score embedded in an overwritten source file.
+#[derive(Deserialize)]
+struct EmbeddedScore {
+    biomarker_id: String,
+    #[serde(flatten)]
+    score: DiffScore,
+}
+
+/// Loads the scores to compare from either a score map file or the source
+/// files matched by the glob pattern after a run in overwrite mode (keyed
+/// the same way as map mode keys them by default).
+pub async fn load_scores(pattern: &str) -> Result<DiffScoreMap, Box<dyn std::error::Error>> {
+    let files = resolve_inputs(&[pattern.to_owned()], &[])?;
+    if files.is_empty() {
+        return Err(format!("No files matched {}", pattern).into());
+    }
+    // The error of reading a single file as a score map, reported if it isn't
+    // a scored source file either
+    let mut score_map_error = None;
+    if let [file] = files.as_slice() {
+        let contents = fs::read_to_string(file).await?;
+        match serde_json::from_str::<DiffScoreMap>(&contents) {
+            Ok(score_map) => return Ok(score_map),
+            Err(e) => score_map_error = Some(e),
+        }
+    }
+
+    let mut score_map = DiffScoreMap::new();
+    for input in key_inputs(&files, &KeyRoot::CommonAncestor)? {
+        let contents = fs::read_to_string(&input.path).await?;
+        let scores = match serde_json::from_str::<Vec<EmbeddedScore>>(&contents) {
+            Ok(scores) => scores,
+            Err(_) => vec![
+                serde_json::from_str::<EmbeddedScore>(&contents).map_err(
+                    |e| match &score_map_error {
+                        Some(score_map_error) => format!(
+                            "{} is neither a score map ({}) nor a scored source file ({})",
+                            input.path.display(),
+                            score_map_error,
+                            e
+                        ),
+                        None => format!(
+                            "{} is not a scored source file: {}",
+                            input.path.display(),
+                            e
+                        ),
+                    },
+                )?,
+            ],
+        };
+        let file_scores = score_map.entry(input.key).or_default();
+        for embedded in scores {
+            file_scores.insert(embedded.biomarker_id, embedded.score);
+        }
+    }
+    Ok(score_map)
+}
+
+/// Compares the old and new scores, matching biomarkers by file key and ID.
+/// Only score changes larger than the threshold are reported.
+pub fn diff_scores(old: &DiffScoreMap, new: &DiffScoreMap, threshold: f64) -> ScoreDiff {
+    let old_ranks = Ranks::new(old);
+    let new_ranks = Ranks::new(new);
+    let mut diff = ScoreDiff::default();
+
+    for (file, old_scores) in old {
+        for (biomarker_id, old_score) in old_scores {
+            let Some(new_score) = new.get(file).and_then(|scores| scores.get(biomarker_id)) else {
+                diff.removed.push(DiffEntry {
+                    file: file.clone(),
+                    biomarker_id: biomarker_id.clone(),
+                    score: old_score.score,
+                });
+                continue;
+            };
+            let delta = new_score.score - old_score.score;
+            let old_rank = old_ranks.rank(old_score.score);
+            let new_rank = new_ranks.rank(new_score.score);
+            if delta.abs() <= threshold {
+                if old_rank == new_rank {
+                    diff.unchanged += 1;
+                } else {
+                    diff.rank_changed.push(RankChange {
+                        file: file.clone(),
+                        biomarker_id: biomarker_id.clone(),
+                        old_score: old_score.score,
+                        new_score: new_score.score,
+                        old_rank,
+                        new_rank,
+                    });
+                }
+                continue;
+            }
+            diff.changed.push(ScoreChange {
+                file: file.clone(),
+                biomarker_id: biomarker_id.clone(),
+                old_score: old_score.score,
+                new_score: new_score.score,
+                delta,
+                old_rank,
+                new_rank,
+                contributions: contribution_changes(old_score, new_score),
+                rules: rule_changes(old_score, new_score),
+            });
+        }
+    }
+    for (file, new_scores) in new {
+        for (biomarker_id, new_score) in new_scores {
+            if !old
+                .get(file)
+                .is_some_and(|scores| scores.contains_key(biomarker_id))
+            {
+                diff.added.push(DiffEntry {
+                    file: file.clone(),
+                    biomarker_id: biomarker_id.clone(),
+                    score: new_score.score,
+                });
+            }
+        }
+    }
+
+    diff.changed
+        .sort_by(|a, b| b.delta.abs().total_cmp(&a.delta.abs()));
+    diff.rank_changed
+        .sort_by_key(|change| std::cmp::Reverse(change.old_rank.abs_diff(change.new_rank)));
+    diff
+}
+
+/// Scores sorted from highest to lowest, for ranking.
+pub(crate) struct Ranks(Vec<f64>);
+
+impl Ranks {
+    fn new(score_map: &DiffScoreMap) -> Self {
+        Ranks::from_scores(
+            score_map
+                .values()
+                .flat_map(|scores| scores.values().map(|score| score.score))
+                .collect(),
+        )
+    }
+
+    pub(crate) fn from_scores(mut scores: Vec<f64>) -> Self {
+        scores.sort_by(|a, b| b.total_cmp(a));
+        Ranks(scores)
+    }
+
+    /// One plus the number of strictly higher scores.
+    pub(crate) fn rank(&self, score: f64) -> usize {
+        self.0.partition_point(|&s| s > score) + 1
+    }
+}
+
+fn contribution_changes(old: &DiffScore, new: &DiffScore) -> Vec<ContributionChange> {
+    let mut contributions: BTreeMap<
+        &str,
+        (Option<&ScoreContribution>, Option<&ScoreContribution>),
+    > = BTreeMap::new();
+    for contribution in &old.score_info.contributions {
+        contributions.entry(&contribution.c).or_default().0 = Some(contribution);
+    }
+    for contribution in &new.score_info.contributions {
+        contributions.entry(&contribution.c).or_default().1 = Some(contribution);
+    }
+
+    let value = |c: Option<&ScoreContribution>| c.map_or(0.0, |c| c.w * c.f);
+    contributions
+        .into_iter()
+        .filter(|(_, (old, new))| value(*old) != value(*new))
+        .map(|(condition, (old, new))| ContributionChange {
+            condition: condition.to_owned(),
+            old_weight: old.map(|c| c.w),
+            new_weight: new.map(|c| c.w),
+            old_frequency: old.map(|c| c.f),
+            new_frequency: new.map(|c| c.f),
+            delta: value(new) - value(old),
+        })
+        .collect()
+}
+
+fn rule_changes(old: &DiffScore, new: &DiffScore) -> Vec<RuleChange> {
+    let mut rules: BTreeMap<&str, (Option<f64>, Option<f64>)> = BTreeMap::new();
+    for rule in old.score_info.custom_rules.iter().flatten() {
+        rules.entry(&rule.rule_name).or_default().0 = Some(rule.effect);
+    }
+    for rule in new.score_info.custom_rules.iter().flatten() {
+        rules.entry(&rule.rule_name).or_default().1 = Some(rule.effect);
+    }
+
+    rules
+        .into_iter()
+        .filter(|(_, (old_effect, new_effect))| old_effect != new_effect)
+        .map(|(rule_name, (old_effect, new_effect))| RuleChange {
+            rule_name: rule_name.to_owned(),
+            old_effect,
+            new_effect,
+        })
+        .collect()
+}
+
+impl fmt::Display for ScoreDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} added, {} removed, {} changed, {} rank changed, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.rank_changed.len(),
+            self.unchanged
+        )?;
+        if !self.added.is_empty() {
+            writeln!(f, "Added:")?;
+            for entry in &self.added {
+                writeln!(
+                    f,
+                    "  {} {}: {}",
+                    entry.file, entry.biomarker_id, entry.score
+                )?;
+            }
+        }
+        if !self.removed.is_empty() {
+            writeln!(f, "Removed:")?;
+            for entry in &self.removed {
+                writeln!(
+                    f,
+                    "  {} {}: {}",
+                    entry.file, entry.biomarker_id, entry.score
+                )?;
+            }
+        }
+        if !self.changed.is_empty() {
+            writeln!(f, "Changed:")?;
+            for change in &self.changed {
+                writeln!(
+                    f,
+                    "  {} {}: {} -> {} ({:+}), rank {} -> {}",
+                    change.file,
+                    change.biomarker_id,
+                    change.old_score,
+                    change.new_score,
+                    change.delta,
+                    change.old_rank,
+                    change.new_rank
+                )?;
+                for contribution in &change.contributions {
+                    writeln!(
+                        f,
+                        "    {}: {} -> {} ({:+})",
+                        contribution.condition,
+                        weighted(contribution.old_weight, contribution.old_frequency),
+                        weighted(contribution.new_weight, contribution.new_frequency),
+                        contribution.delta
+                    )?;
+                }
+                for rule in &change.rules {
+                    writeln!(
+                        f,
+                        "    rule {}: {} -> {}",
+                        rule.rule_name,
+                        effect(rule.old_effect),
+                        effect(rule.new_effect)
+                    )?;
+                }
+            }
+        }
+        if !self.rank_changed.is_empty() {
+            writeln!(f, "Rank changed:")?;
+            for change in &self.rank_changed {
+                writeln!(
+                    f,
+                    "  {} {}: {} -> {}, rank {} -> {}",
+                    change.file,
+                    change.biomarker_id,
+                    change.old_score,
+                    change.new_score,
+                    change.old_rank,
+                    change.new_rank
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn weighted(weight: Option<f64>, frequency: Option<f64>) -> String {
+    match (weight, frequency) {
+        (Some(w), Some(f)) => format!("{}*{}", w, f),
+        _ => "none".to_owned(),
+    }
+}
+
+fn effect(effect: Option<f64>) -> String {
+    match effect {
+        Some(effect) => format!("{:+}", effect),
+        None => "not applied".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A score map as written by the first release, with the rule action as a
+    /// string and without rule priorities.
+    const BASELINE_MAP: &str = r#"{
+      "data.json": {
+        "AN6278-1": {
+          "score": -5.0,
+          "score_info": {
+            "contributions": [
+              { "c": "first_pmid", "w": 1.0, "f": 1.0 },
+              { "c": "generic_condition_pen", "w": -4.0, "f": 1.0 }
+            ],
+            "formula": "sum(w*f)",
+            "variables": { "w": "weight", "f": "frequency", "c": "condition" },
+            "custom_rules": [
+              {
+                "rule_name": "AN6278-1 hardcode -5",
+                "condition": { "type": "Simple", "content": "BiomarkerID == AN6278-1" },
+                "action": "SetScore(-5.0)",
+                "effect": -5.0
+              }
+            ]
+          }
+        },
+        "AN0001-2": {
+          "score": 2.2,
+          "score_info": {
+            "contributions": [{ "c": "first_pmid", "w": 1.0, "f": 2.2 }],
+            "formula": "sum(w*f)",
+            "variables": { "w": "weight", "f": "frequency", "c": "condition" },
+            "custom_rules": null
+          }
+        }
+      }
+    }"#;
+
+    const CURRENT_MAP: &str = r#"{
+      "data.json": {
+        "AN6278-1": {
+          "score": -3.0,
+          "score_info": {
+            "contributions": [
+              { "c": "first_pmid", "w": 1.0, "f": 1.0 },
+              { "c": "generic_condition_pen", "w": -4.0, "f": 1.0 }
+            ],
+            "formula": "sum(w*f)",
+            "variables": { "c": "condition", "f": "frequency", "w": "weight" },
+            "base_score": 0.0,
+            "clamp": null,
+            "custom_rules": [
+              {
+                "rule_name": "AN6278-1 hardcode -3",
+                "priority": 1,
+                "condition": { "type": "FieldEquals", "field": "BiomarkerID", "value": "AN6278-1", "matched": true },
+                "action": { "type": "SetScore", "value": -3.0 },
+                "effect": -3.0
+              }
+            ]
+          }
+        },
+        "AN0001-2": {
+          "score": 2.2,
+          "score_info": {
+            "contributions": [{ "c": "first_pmid", "w": 1.0, "f": 2.2 }],
+            "formula": "sum(w*f)",
+            "variables": { "c": "condition", "f": "frequency", "w": "weight" },
+            "base_score": 2.2,
+            "custom_rules": null
+          }
+        }
+      }
+    }"#;
+
+    #[test]
+    fn diff_against_baseline_map() {
+        let old: DiffScoreMap = serde_json::from_str(BASELINE_MAP).unwrap();
+        let new: DiffScoreMap = serde_json::from_str(CURRENT_MAP).unwrap();
+        let diff = diff_scores(&old, &new, 0.0);
+
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(diff.changed.len(), 1);
+        let change = &diff.changed[0];
+        assert_eq!(change.biomarker_id, "AN6278-1");
+        assert_eq!(change.delta, 2.0);
+        assert!(change.contributions.is_empty());
+        let rules: Vec<_> = change
+            .rules
+            .iter()
+            .map(|rule| (rule.rule_name.as_str(), rule.old_effect, rule.new_effect))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("AN6278-1 hardcode -3", None, Some(-3.0)),
+                ("AN6278-1 hardcode -5", Some(-5.0), None),
+            ]
+        );
+    }
+}
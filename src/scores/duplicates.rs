@@ -0,0 +1,166 @@
+//! Duplicates Module
+//!
+//! Detection of biomarkers sharing an ID, and the policy for handling
+//! duplicates within a source file in map mode and compare.
+
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// What to do with biomarkers sharing an ID within a source file.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Fail on any duplicate ID, within a file or across files.
+    Error,
+    /// Keep the first record with the ID.
+    KeepFirst,
+    /// Keep the last record with the ID.
+    #[default]
+    KeepLast,
+    /// Merge the components and evidence of all the records before scoring.
+    Merge,
+}
+
+impl DuplicatePolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(DuplicatePolicy::Error),
+            "keep-first" => Some(DuplicatePolicy::KeepFirst),
+            "keep-last" => Some(DuplicatePolicy::KeepLast),
+            "merge" => Some(DuplicatePolicy::Merge),
+            _ => None,
+        }
+    }
+}
+
+/// The duplicate biomarker IDs found in a run.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DuplicateReport {
+    /// IDs found more than once within a single file.
+    pub within_files: Vec<FileDuplicate>,
+    /// IDs found in more than one file.
+    pub across_files: Vec<CrossFileDuplicate>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FileDuplicate {
+    pub file: String,
+    pub biomarker_id: String,
+    pub occurrences: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CrossFileDuplicate {
+    pub biomarker_id: String,
+    pub files: Vec<String>,
+}
+
+impl DuplicateReport {
+    pub fn is_empty(&self) -> bool {
+        self.within_files.is_empty() && self.across_files.is_empty()
+    }
+}
+
+/// Applies the duplicate policy to the biomarkers of a single file, recording
+/// any duplicates found in the report.
+pub fn dedupe_biomarkers(
+    biomarkers: Vec<MinBiomarker>,
+    policy: DuplicatePolicy,
+    file: &str,
+    report: &mut DuplicateReport,
+) -> Result<Vec<MinBiomarker>, Box<dyn std::error::Error>> {
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut occurrences: HashMap<String, usize> = HashMap::new();
+    let mut deduped: Vec<MinBiomarker> = Vec::with_capacity(biomarkers.len());
+
+    for biomarker in biomarkers {
+        match positions.get(&biomarker.biomarker_id) {
+            None => {
+                positions.insert(biomarker.biomarker_id.clone(), deduped.len());
+                deduped.push(biomarker);
+            }
+            Some(&position) => {
+                *occurrences
+                    .entry(biomarker.biomarker_id.clone())
+                    .or_insert(1) += 1;
+                match policy {
+                    DuplicatePolicy::Error => {
+                        return Err(format!(
+                            "Duplicate biomarker ID `{}` in {}",
+                            biomarker.biomarker_id, file
+                        )
+                        .into());
+                    }
+                    DuplicatePolicy::KeepFirst => {}
+                    DuplicatePolicy::KeepLast => deduped[position] = biomarker,
+                    DuplicatePolicy::Merge => deduped[position].merge(biomarker),
+                }
+            }
+        }
+    }
+
+    let mut duplicates: Vec<FileDuplicate> = occurrences
+        .into_iter()
+        .map(|(biomarker_id, occurrences)| FileDuplicate {
+            file: file.to_owned(),
+            biomarker_id,
+            occurrences,
+        })
+        .collect();
+    duplicates.sort_by(|a, b| a.biomarker_id.cmp(&b.biomarker_id));
+    report.within_files.extend(duplicates);
+
+    Ok(deduped)
+}
+
+/// Finds the biomarker IDs scored in more than one file of the score map.
+pub fn find_cross_file_duplicates(score_map: &ScoreMap) -> Vec<CrossFileDuplicate> {
+    cross_file_duplicates(score_map.iter().flat_map(|(file, scores)| {
+        scores
+            .keys()
+            .map(move |biomarker_id| (file.as_str(), biomarker_id.as_str()))
+    }))
+}
+
+/// Finds the biomarker IDs in more than one file, from `(file, biomarker_id)` pairs.
+pub fn cross_file_duplicates<'a>(
+    ids: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Vec<CrossFileDuplicate> {
+    let mut files_by_id: HashMap<&str, Vec<String>> = HashMap::new();
+    for (file, biomarker_id) in ids {
+        files_by_id
+            .entry(biomarker_id)
+            .or_default()
+            .push(file.to_owned());
+    }
+    let mut duplicates: Vec<CrossFileDuplicate> = files_by_id
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(biomarker_id, mut files)| {
+            files.sort();
+            CrossFileDuplicate {
+                biomarker_id: biomarker_id.to_owned(),
+                files,
+            }
+        })
+        .collect();
+    duplicates.sort_by(|a, b| a.biomarker_id.cmp(&b.biomarker_id));
+    duplicates
+}
+
+/// Fails on the first ID found in more than one file under the `error` policy.
+pub fn check_cross_file_duplicates(
+    policy: DuplicatePolicy,
+    duplicates: &[CrossFileDuplicate],
+) -> Result<(), Box<dyn std::error::Error>> {
+    match duplicates.first() {
+        Some(duplicate) if policy == DuplicatePolicy::Error => Err(format!(
+            "Duplicate biomarker ID `{}` in {}",
+            duplicate.biomarker_id,
+            duplicate.files.join(", ")
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,197 @@
+//! Explain Module
+//!
+//! Step-by-step traces of the score computation for a single biomarker, used
+//! to debug scores without rerunning and grepping the whole score map.
+
+use crate::models::traits::BiomarkerData;
+use crate::models::CustomCondition;
+use crate::prelude::*;
+use crate::scores::calculate::score_biomarker;
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use tokio::fs;
+
+pub use crate::rules::engine::RuleStep;
+
+/// The full trace of a single biomarker score computation.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ScoreTrace {
+    /// The source file the biomarker was read from.
+    pub file: String,
+    pub biomarker_id: String,
+    /// Every evidence item considered, in the order it was seen.
+    pub evidence: Vec<EvidenceStep>,
+    /// The remaining scoring criteria and score adjustments.
+    pub steps: Vec<ScoreStep>,
+    /// The score before any custom rules are applied.
+    pub base_score: f64,
+    /// Every custom rule in priority order, whether it matched or not.
+    pub rules: Vec<RuleStep>,
+    /// The score adjustments after the custom rules.
+    pub final_steps: Vec<ScoreStep>,
+    pub final_score: f64,
+}
+
+/// How a single evidence item was counted.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EvidenceOutcome {
+    FirstPmid,
+    OtherPmid,
+    /// A unique PubMed evidence past the `pmid_limit`, not scored.
+    PmidLimit,
+    FirstSource,
+    OtherSource,
+    /// The evidence id was already seen, not scored.
+    Duplicate,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EvidenceStep {
+    /// The component index the evidence belongs to, `None` for top level evidence.
+    pub component: Option<usize>,
+    pub database: String,
+    pub id: String,
+    pub outcome: EvidenceOutcome,
+    pub running_score: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ScoreStep {
+    pub description: String,
+    pub running_score: f64,
+}
+
+/// Calculates the score for a biomarker and returns the full trace of the computation.
+pub fn explain_score<B>(
+    biomarker: &B,
+    weights: &Weights,
+    custom_rules: Option<&CustomRules>,
+) -> ScoreTrace
+where
+    B: BiomarkerData,
+    B::Evidence: AsRef<B::Evidence>,
+    B::Component: AsRef<B::Component>,
+{
+    let mut trace = ScoreTrace {
+        biomarker_id: biomarker.biomarker_id().to_owned(),
+        ..Default::default()
+    };
+    score_biomarker(biomarker, weights, custom_rules, Some(&mut trace));
+    trace
+}
+
+/// Searches the input files for the biomarker and traces the score computation
+/// of every occurrence found.
+pub async fn explain_biomarker(
+    files: &[PathBuf],
+    biomarker_id: &str,
+    weights: &Weights,
+    custom_rules: Option<&CustomRules>,
+) -> Result<Vec<ScoreTrace>, Box<dyn std::error::Error>> {
+    let mut traces = Vec::new();
+
+    for path in files {
+        let contents = fs::read_to_string(path).await?;
+        let biomarkers = match serde_json::from_str::<Vec<MinBiomarker>>(&contents) {
+            Ok(biomarkers) => biomarkers,
+            Err(_) => vec![serde_json::from_str::<MinBiomarker>(&contents)?],
+        };
+        for biomarker in biomarkers.iter().filter(|b| b.biomarker_id == biomarker_id) {
+            let mut trace = explain_score(biomarker, weights, custom_rules);
+            trace.file = path.display().to_string();
+            traces.push(trace);
+        }
+    }
+
+    Ok(traces)
+}
+
+impl fmt::Display for EvidenceOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self {
+            EvidenceOutcome::FirstPmid => "counted as first_pmid",
+            EvidenceOutcome::OtherPmid => "counted as other_pmid",
+            EvidenceOutcome::PmidLimit => "skipped, pmid_limit reached",
+            EvidenceOutcome::FirstSource => "counted as first_source",
+            EvidenceOutcome::OtherSource => "counted as other_source",
+            EvidenceOutcome::Duplicate => "skipped, duplicate",
+        };
+        write!(f, "{}", outcome)
+    }
+}
+
+impl fmt::Display for ScoreTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Biomarker {} ({})", self.biomarker_id, self.file)?;
+        writeln!(f, "Evidence:")?;
+        for step in &self.evidence {
+            let location = match step.component {
+                Some(idx) => format!("component {}", idx),
+                None => "top level".to_owned(),
+            };
+            writeln!(
+                f,
+                "  {}:{} [{}] {} -> {}",
+                step.database, step.id, location, step.outcome, step.running_score
+            )?;
+        }
+        writeln!(f, "Criteria:")?;
+        for step in &self.steps {
+            writeln!(f, "  {} -> {}", step.description, step.running_score)?;
+        }
+        writeln!(f, "Base score: {}", self.base_score)?;
+        if !self.rules.is_empty() {
+            writeln!(f, "Rules:")?;
+            for rule in &self.rules {
+                let outcome = match rule.effect {
+                    Some(effect) => format!("matched, effect {}", effect),
+                    None => "not matched".to_owned(),
+                };
+                writeln!(
+                    f,
+                    "  [{}] {}: {} -> {}",
+                    rule.priority, rule.rule_name, outcome, rule.running_score
+                )?;
+                write_condition(f, &rule.condition, 4)?;
+                writeln!(
+                    f,
+                    "    action: {} {}",
+                    rule.action.type_name(),
+                    rule.action.value()
+                )?;
+            }
+        }
+        for step in &self.final_steps {
+            writeln!(f, "  {} -> {}", step.description, step.running_score)?;
+        }
+        write!(f, "Final score: {}", self.final_score)
+    }
+}
+
+fn write_condition(
+    f: &mut fmt::Formatter<'_>,
+    condition: &CustomCondition,
+    indent: usize,
+) -> fmt::Result {
+    let mut line = condition.condition_type.clone();
+    if let Some(field) = &condition.field {
+        line.push_str(&format!(" {}", field));
+    }
+    if let Some(value) = &condition.value {
+        line.push_str(&format!(" {}", value));
+    }
+    writeln!(
+        f,
+        "{:indent$}{} => {}",
+        "",
+        line,
+        condition.matched,
+        indent = indent
+    )?;
+    for sub_condition in condition.conditions.iter().flatten() {
+        write_condition(f, sub_condition, indent + 2)?;
+    }
+    Ok(())
+}
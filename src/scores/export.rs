@@ -0,0 +1,462 @@
+//! Export Module
+//!
+//! Writes the score map as JSON, as a flat CSV/TSV table with one row per
+//! biomarker for spreadsheets and R, or as a Parquet file for loading large
+//! score tables into an analytics warehouse, or into a SQLite database for
+//! querying alongside other biomarker metadata.
+
+use crate::prelude::*;
+use serde::Deserialize;
+
+/// The score map output file format.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    Csv,
+    Tsv,
+    /// Only available when built with the `parquet` feature.
+    Parquet,
+    /// Only available when built with the `sqlite` feature.
+    Sqlite,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(OutputFormat::Json),
+            "csv" => Some(OutputFormat::Csv),
+            "tsv" => Some(OutputFormat::Tsv),
+            "parquet" => Some(OutputFormat::Parquet),
+            "sqlite" => Some(OutputFormat::Sqlite),
+            _ => None,
+        }
+    }
+
+    /// The cargo feature the format needs, if this build was compiled without it.
+    pub fn missing_feature(&self) -> Option<&'static str> {
+        match self {
+            OutputFormat::Parquet if !cfg!(feature = "parquet") => Some("parquet"),
+            OutputFormat::Sqlite if !cfg!(feature = "sqlite") => Some("sqlite"),
+            _ => None,
+        }
+    }
+
+    /// The file extension of the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Sqlite => "sqlite",
+        }
+    }
+}
+
+/// Writes the score map to the file in the output format. The SQLite format
+/// upserts into an existing database instead of replacing the file.
+pub async fn write_score_map(
+    score_map: &ScoreMap,
+    path: &str,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = match format {
+        OutputFormat::Json => serde_json::to_vec_pretty(score_map)?,
+        OutputFormat::Csv => ScoreTable::new(score_map).to_csv(b',')?,
+        OutputFormat::Tsv => ScoreTable::new(score_map).to_csv(b'\t')?,
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => ScoreTable::new(score_map).to_parquet()?,
+        #[cfg(not(feature = "parquet"))]
+        OutputFormat::Parquet => {
+            return Err("Parquet output requires building with the `parquet` feature".into())
+        }
+        #[cfg(feature = "sqlite")]
+        OutputFormat::Sqlite => return upsert_sqlite(score_map, path),
+        #[cfg(not(feature = "sqlite"))]
+        OutputFormat::Sqlite => {
+            return Err("SQLite output requires building with the `sqlite` feature".into())
+        }
+    };
+    tokio::fs::write(path, contents).await?;
+    Ok(())
+}
+
+/// The score map flattened into one row per biomarker, sorted by file and
+/// biomarker ID. Every contribution gets a frequency (`_f`) and a weight
+/// (`_w`) column.
+struct ScoreTable<'a> {
+    rows: Vec<(&'a str, &'a str, &'a BiomarkerScore)>,
+    /// The contribution conditions in the order they are first seen.
+    conditions: Vec<&'a str>,
+    /// Whether any score has a provenance (aggregate mode).
+    with_provenance: bool,
+    /// Whether the scores were normalised.
+    with_normalization: bool,
+}
+
+impl<'a> ScoreTable<'a> {
+    fn new(score_map: &'a ScoreMap) -> Self {
+        let rows: Vec<(&str, &str, &BiomarkerScore)> = score_map
+            .iter()
+            .flat_map(|(file, scores)| {
+                scores
+                    .iter()
+                    .map(move |(id, score)| (file.as_str(), id.as_str(), score))
+            })
+            .collect();
+
+        let mut conditions: Vec<&str> = Vec::new();
+        for (_, _, score) in &rows {
+            for contribution in &score.score_info.contributions {
+                if !conditions.contains(&contribution.c.as_str()) {
+                    conditions.push(&contribution.c);
+                }
+            }
+        }
+        let with_provenance = rows.iter().any(|(_, _, score)| score.provenance.is_some());
+        let with_normalization = rows
+            .iter()
+            .any(|(_, _, score)| score.normalized_score.is_some());
+
+        ScoreTable {
+            rows,
+            conditions,
+            with_provenance,
+            with_normalization,
+        }
+    }
+
+    fn header(&self) -> Vec<String> {
+        let mut header = vec![
+            "file".to_owned(),
+            "biomarker_id".to_owned(),
+            "score".to_owned(),
+            "base_score".to_owned(),
+        ];
+        if self.with_normalization {
+            header.push("normalized_score".to_owned());
+            header.push("percentile".to_owned());
+            header.push("tier".to_owned());
+        }
+        for condition in &self.conditions {
+            header.push(format!("{}_f", condition));
+            header.push(format!("{}_w", condition));
+        }
+        header.push("custom_rules".to_owned());
+        if self.with_provenance {
+            header.push("provenance".to_owned());
+        }
+        header
+    }
+
+    /// Writes the table as CSV, joining the applied custom rules and the
+    /// provenance with `;`.
+    fn to_csv(&self, delimiter: u8) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .from_writer(Vec::new());
+        writer.write_record(self.header())?;
+
+        for (file, biomarker_id, score) in &self.rows {
+            let mut record = vec![
+                file.to_string(),
+                biomarker_id.to_string(),
+                score.score.to_string(),
+                score.score_info.base_score.to_string(),
+            ];
+            if self.with_normalization {
+                let optional =
+                    |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+                record.push(optional(score.normalized_score));
+                record.push(optional(score.percentile));
+                record.push(score.tier.clone().unwrap_or_default());
+            }
+            for condition in &self.conditions {
+                match find_contribution(score, condition) {
+                    Some(contribution) => {
+                        record.push(contribution.f.to_string());
+                        record.push(contribution.w.to_string());
+                    }
+                    None => record.extend([String::new(), String::new()]),
+                }
+            }
+            record.push(rule_names(score).join(";"));
+            if self.with_provenance {
+                record.push(
+                    score
+                        .provenance
+                        .iter()
+                        .flatten()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(";"),
+                );
+            }
+            writer.write_record(&record)?;
+        }
+
+        Ok(writer.into_inner().map_err(|e| e.into_error())?)
+    }
+
+    /// Writes the table as a Parquet file, with list columns for the applied
+    /// custom rules and the provenance.
+    #[cfg(feature = "parquet")]
+    fn to_parquet(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        use arrow_array::builder::{ListBuilder, StringBuilder};
+        use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray};
+        use parquet::arrow::ArrowWriter;
+        use std::sync::Arc;
+
+        let header = self.header();
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(
+                self.rows.iter().map(|row| row.0),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                self.rows.iter().map(|row| row.1),
+            )),
+            Arc::new(Float64Array::from_iter_values(
+                self.rows.iter().map(|row| row.2.score),
+            )),
+            Arc::new(Float64Array::from_iter_values(
+                self.rows.iter().map(|row| row.2.score_info.base_score),
+            )),
+        ];
+        if self.with_normalization {
+            columns.push(Arc::new(Float64Array::from_iter(
+                self.rows.iter().map(|row| row.2.normalized_score),
+            )));
+            columns.push(Arc::new(Float64Array::from_iter(
+                self.rows.iter().map(|row| row.2.percentile),
+            )));
+            columns.push(Arc::new(StringArray::from_iter(
+                self.rows.iter().map(|row| row.2.tier.as_deref()),
+            )));
+        }
+        for condition in &self.conditions {
+            let contributions: Vec<Option<&ScoreContribution>> = self
+                .rows
+                .iter()
+                .map(|row| find_contribution(row.2, condition))
+                .collect();
+            columns.push(Arc::new(Float64Array::from_iter(
+                contributions.iter().map(|c| c.map(|c| c.f)),
+            )));
+            columns.push(Arc::new(Float64Array::from_iter(
+                contributions.iter().map(|c| c.map(|c| c.w)),
+            )));
+        }
+        let mut custom_rules = ListBuilder::new(StringBuilder::new());
+        for (_, _, score) in &self.rows {
+            custom_rules.append_value(rule_names(score).into_iter().map(Some));
+        }
+        columns.push(Arc::new(custom_rules.finish()));
+        if self.with_provenance {
+            let mut provenance = ListBuilder::new(StringBuilder::new());
+            for (_, _, score) in &self.rows {
+                provenance.append_option(
+                    score
+                        .provenance
+                        .as_ref()
+                        .map(|files| files.iter().map(Some)),
+                );
+            }
+            columns.push(Arc::new(provenance.finish()));
+        }
+
+        let batch = RecordBatch::try_from_iter(header.into_iter().zip(columns))?;
+        let mut writer = ArrowWriter::try_new(Vec::new(), batch.schema(), None)?;
+        writer.write(&batch)?;
+        Ok(writer.into_inner()?)
+    }
+}
+
+/// Creates the `biomarkers`, `contributions` and `rule_applications` tables if
+/// needed and upserts the scores, replacing the contributions and applied
+/// rules of every biomarker in the score map. Biomarkers from files that are
+/// not in the score map are left untouched.
+#[cfg(feature = "sqlite")]
+fn upsert_sqlite(score_map: &ScoreMap, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    use rusqlite::{params, Connection};
+
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS biomarkers (
+            file TEXT NOT NULL,
+            biomarker_id TEXT NOT NULL,
+            score REAL NOT NULL,
+            base_score REAL NOT NULL,
+            formula TEXT NOT NULL,
+            provenance TEXT,
+            normalized_score REAL,
+            percentile REAL,
+            tier TEXT,
+            PRIMARY KEY (file, biomarker_id)
+        );
+        CREATE TABLE IF NOT EXISTS contributions (
+            file TEXT NOT NULL,
+            biomarker_id TEXT NOT NULL,
+            condition TEXT NOT NULL,
+            weight REAL NOT NULL,
+            frequency REAL NOT NULL,
+            PRIMARY KEY (file, biomarker_id, condition)
+        );
+        CREATE TABLE IF NOT EXISTS rule_applications (
+            file TEXT NOT NULL,
+            biomarker_id TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            rule_name TEXT NOT NULL,
+            priority INTEGER NOT NULL,
+            condition TEXT NOT NULL,
+            action TEXT NOT NULL,
+            effect REAL NOT NULL,
+            PRIMARY KEY (file, biomarker_id, position)
+        );
+        CREATE INDEX IF NOT EXISTS biomarkers_biomarker_id ON biomarkers (biomarker_id);
+        CREATE INDEX IF NOT EXISTS biomarkers_file ON biomarkers (file);
+        CREATE INDEX IF NOT EXISTS contributions_biomarker_id ON contributions (biomarker_id);
+        CREATE INDEX IF NOT EXISTS contributions_file ON contributions (file);
+        CREATE INDEX IF NOT EXISTS rule_applications_biomarker_id ON rule_applications (biomarker_id);
+        CREATE INDEX IF NOT EXISTS rule_applications_file ON rule_applications (file);",
+    )?;
+    // Databases written before the normalisation columns existed
+    let columns = connection
+        .prepare("SELECT name FROM pragma_table_info('biomarkers')")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (column, column_type) in [
+        ("normalized_score", "REAL"),
+        ("percentile", "REAL"),
+        ("tier", "TEXT"),
+    ] {
+        if !columns.iter().any(|c| c == column) {
+            connection.execute(
+                &format!(
+                    "ALTER TABLE biomarkers ADD COLUMN {} {}",
+                    column, column_type
+                ),
+                [],
+            )?;
+        }
+    }
+
+    let transaction = connection.transaction()?;
+    {
+        let mut upsert_biomarker = transaction.prepare(
+            "INSERT INTO biomarkers
+                (file, biomarker_id, score, base_score, formula, provenance,
+                normalized_score, percentile, tier)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            ON CONFLICT (file, biomarker_id) DO UPDATE SET
+                score = excluded.score,
+                base_score = excluded.base_score,
+                formula = excluded.formula,
+                provenance = excluded.provenance,
+                normalized_score = excluded.normalized_score,
+                percentile = excluded.percentile,
+                tier = excluded.tier",
+        )?;
+        let mut delete_contributions = transaction
+            .prepare("DELETE FROM contributions WHERE file = ?1 AND biomarker_id = ?2")?;
+        let mut delete_rule_applications = transaction
+            .prepare("DELETE FROM rule_applications WHERE file = ?1 AND biomarker_id = ?2")?;
+        let mut insert_contribution = transaction.prepare(
+            "INSERT INTO contributions (file, biomarker_id, condition, weight, frequency)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        let mut insert_rule_application = transaction.prepare(
+            "INSERT INTO rule_applications
+                (file, biomarker_id, position, rule_name, priority, condition, action, effect)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+
+        let mut select_biomarker_ids =
+            transaction.prepare("SELECT biomarker_id FROM biomarkers WHERE file = ?1")?;
+        let mut delete_biomarker =
+            transaction.prepare("DELETE FROM biomarkers WHERE file = ?1 AND biomarker_id = ?2")?;
+
+        for (file, scores) in score_map {
+            // Biomarkers no longer in a re-scored file are removed
+            let stored_ids = select_biomarker_ids
+                .query_map(params![file], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            for biomarker_id in stored_ids
+                .iter()
+                .filter(|id| !scores.contains_key(id.as_str()))
+            {
+                delete_biomarker.execute(params![file, biomarker_id])?;
+                delete_contributions.execute(params![file, biomarker_id])?;
+                delete_rule_applications.execute(params![file, biomarker_id])?;
+            }
+
+            for (biomarker_id, score) in scores {
+                let provenance = score
+                    .provenance
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?;
+                upsert_biomarker.execute(params![
+                    file,
+                    biomarker_id,
+                    score.score,
+                    score.score_info.base_score,
+                    score.score_info.formula,
+                    provenance,
+                    score.normalized_score,
+                    score.percentile,
+                    score.tier
+                ])?;
+                delete_contributions.execute(params![file, biomarker_id])?;
+                delete_rule_applications.execute(params![file, biomarker_id])?;
+                for contribution in &score.score_info.contributions {
+                    insert_contribution.execute(params![
+                        file,
+                        biomarker_id,
+                        contribution.c,
+                        contribution.w,
+                        contribution.f
+                    ])?;
+                }
+                for (position, rule) in score.score_info.custom_rules.iter().flatten().enumerate() {
+                    insert_rule_application.execute(params![
+                        file,
+                        biomarker_id,
+                        position as i64,
+                        rule.rule_name,
+                        rule.priority,
+                        serde_json::to_string(&rule.condition)?,
+                        serde_json::to_string(&rule.action)?,
+                        rule.effect
+                    ])?;
+                }
+            }
+        }
+    }
+    transaction.commit()?;
+
+    Ok(())
+}
+
+fn find_contribution<'a>(
+    score: &'a BiomarkerScore,
+    condition: &str,
+) -> Option<&'a ScoreContribution> {
+    score
+        .score_info
+        .contributions
+        .iter()
+        .find(|contribution| contribution.c == condition)
+}
+
+/// The names of the custom rules applied to the score, in application order.
+fn rule_names(score: &BiomarkerScore) -> Vec<&str> {
+    score
+        .score_info
+        .custom_rules
+        .iter()
+        .flatten()
+        .map(|rule| rule.rule_name.as_str())
+        .collect()
+}
@@ -0,0 +1,121 @@
+//! LOINC Module
+//!
+//! Validates the LOINC codes of the specimens, so placeholder values like
+//! `N/A` or `-` don't earn the LOINC score.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Why a LOINC code is invalid.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoincIssue {
+    /// Not 1 to 7 digits, a hyphen and a check digit.
+    Format,
+    /// The check digit doesn't match the code.
+    CheckDigit,
+    /// Not found in the configured LOINC table.
+    NotInTable,
+}
+
+/// Validates the (trimmed) code against the LOINC format and check digit, and
+/// against the LOINC table if given.
+pub fn validate_loinc(code: &str, table: Option<&HashSet<String>>) -> Result<(), LoincIssue> {
+    let Some((number, check)) = code.split_once('-') else {
+        return Err(LoincIssue::Format);
+    };
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if number.is_empty() || number.len() > 7 || !is_digits(number) {
+        return Err(LoincIssue::Format);
+    }
+    if check.len() != 1 || !is_digits(check) {
+        return Err(LoincIssue::Format);
+    }
+    if check_digit(number) != check.as_bytes()[0] - b'0' {
+        return Err(LoincIssue::CheckDigit);
+    }
+    if table.is_some_and(|table| !table.contains(code)) {
+        return Err(LoincIssue::NotInTable);
+    }
+    Ok(())
+}
+
+/// The LOINC mod 10 check digit: starting from the rightmost digit, every
+/// other digit is doubled, and the check digit brings the sum of all the
+/// resulting digits up to a multiple of 10.
+fn check_digit(number: &str) -> u8 {
+    let sum: u32 = number
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(idx, b)| {
+            let digit = (b - b'0') as u32;
+            if idx % 2 == 0 {
+                let doubled = digit * 2;
+                doubled / 10 + doubled % 10
+            } else {
+                digit
+            }
+        })
+        .sum();
+    ((10 - sum % 10) % 10) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_digits() {
+        assert_eq!(check_digit("26881"), 3);
+        assert_eq!(check_digit("12345"), 5);
+        assert_eq!(check_digit("2345"), 7);
+        assert_eq!(check_digit("718"), 7);
+        assert_eq!(check_digit("0"), 0);
+    }
+
+    #[test]
+    fn valid_codes() {
+        assert_eq!(validate_loinc("26881-3", None), Ok(()));
+        assert_eq!(validate_loinc("12345-5", None), Ok(()));
+        assert_eq!(validate_loinc("718-7", None), Ok(()));
+    }
+
+    #[test]
+    fn invalid_codes() {
+        assert_eq!(validate_loinc("12345-6", None), Err(LoincIssue::CheckDigit));
+        for code in [
+            "N/A",
+            "-",
+            "",
+            "12345",
+            "-5",
+            "12345-",
+            "12345-55",
+            "1234a-5",
+            "12345678-0",
+        ] {
+            assert_eq!(
+                validate_loinc(code, None),
+                Err(LoincIssue::Format),
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn table_lookup() {
+        let table: HashSet<String> = ["26881-3".to_owned()].into();
+        assert_eq!(validate_loinc("26881-3", Some(&table)), Ok(()));
+        assert_eq!(
+            validate_loinc("12345-5", Some(&table)),
+            Err(LoincIssue::NotInTable)
+        );
+        // The format and check digit are checked first
+        assert_eq!(
+            validate_loinc("12345-6", Some(&table)),
+            Err(LoincIssue::CheckDigit)
+        );
+    }
+}
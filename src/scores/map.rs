@@ -1,44 +1,67 @@
+use crate::inputs::InputFile;
 use crate::prelude::*;
+use crate::scores::duplicates::{
+    check_cross_file_duplicates, dedupe_biomarkers, find_cross_file_duplicates, DuplicatePolicy,
+    DuplicateReport,
+};
 use crate::CHECKPOINT;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::path::Path;
 use tokio::fs;
 
+/// The score map, keyed by source file key (see `inputs::key_inputs`) and then biomarker ID.
+/// Both levels are sorted so the output is the same from run to run.
+pub type ScoreMap = BTreeMap<String, BTreeMap<String, BiomarkerScore>>;
+
 pub async fn generate_score_map(
-    glob_pattern: &str,
+    inputs: &[InputFile],
     weights: &Weights,
     custom_rules: Option<CustomRules>,
-) -> Result<HashMap<String, HashMap<String, BiomarkerScore>>, Box<dyn std::error::Error>> {
-    let mut score_map = HashMap::new();
-    let files = glob::glob(glob_pattern)?;
-
-    for (idx, file) in files.enumerate() {
-        let path = file?;
+    duplicate_policy: DuplicatePolicy,
+) -> Result<(ScoreMap, DuplicateReport), Box<dyn std::error::Error>> {
+    let mut score_map = BTreeMap::new();
+    let mut duplicates = DuplicateReport::default();
+    for (idx, input) in inputs.iter().enumerate() {
         if idx % CHECKPOINT == 0 {
             println!("Checkpoint reached at file index: {}", idx + 1);
         }
-        process_file(&path, weights, &mut score_map, custom_rules.as_ref()).await?;
+        process_file(
+            &input.path,
+            &input.key,
+            weights,
+            &mut score_map,
+            custom_rules.as_ref(),
+            duplicate_policy,
+            &mut duplicates,
+        )
+        .await?;
     }
 
-    Ok(score_map)
+    duplicates.across_files = find_cross_file_duplicates(&score_map);
+    check_cross_file_duplicates(duplicate_policy, &duplicates.across_files)?;
+
+    Ok((score_map, duplicates))
 }
 
 async fn process_file(
     path: &Path,
+    key: &str,
     weights: &Weights,
-    score_map: &mut HashMap<String, HashMap<String, BiomarkerScore>>,
+    score_map: &mut ScoreMap,
     custom_rules: Option<&CustomRules>,
+    duplicate_policy: DuplicatePolicy,
+    duplicates: &mut DuplicateReport,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let filename = path.file_name().unwrap().to_string_lossy().into_owned();
     let contents = fs::read_to_string(path).await?;
     let biomarkers: Vec<MinBiomarker> = serde_json::from_str(&contents)?;
+    let biomarkers = dedupe_biomarkers(biomarkers, duplicate_policy, key, duplicates)?;
 
-    let file_scores = score_map.entry(filename).or_insert_with(HashMap::new);
+    let file_scores = score_map.entry(key.to_owned()).or_default();
     for biomarker in biomarkers {
         let (score, score_info) = calculate_score(&biomarker, weights, custom_rules);
         file_scores.insert(
             biomarker.biomarker_id.clone(),
-            BiomarkerScore { score, score_info },
+            BiomarkerScore::new(score, score_info),
         );
     }
 
@@ -0,0 +1,158 @@
+//! Normalize Module
+//!
+//! Optional post-processing of the scores of a run: a normalised score between
+//! 0 and 1, the percentile rank within the run, and a tier label picked by
+//! thresholds on the normalised score, so a UI can show a stable evidence level.
+
+use crate::prelude::*;
+use serde::Deserialize;
+
+/// The default steepness of the logistic curve.
+pub const DEFAULT_STEEPNESS: f64 = 1.0;
+
+/// How the raw scores are mapped to 0-1.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NormalizationMethod {
+    /// Linearly between the lowest and highest score of the run.
+    #[default]
+    MinMax,
+    /// Along a logistic curve, `1 / (1 + e^(-steepness * (score - midpoint)))`.
+    Logistic,
+}
+
+impl NormalizationMethod {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "min-max" => Some(NormalizationMethod::MinMax),
+            "logistic" => Some(NormalizationMethod::Logistic),
+            _ => None,
+        }
+    }
+}
+
+/// The normalisation settings.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Normalization {
+    #[serde(default)]
+    pub method: NormalizationMethod,
+    /// The score mapped to 0.5 by the logistic curve, defaults to the median score of the run.
+    pub midpoint: Option<f64>,
+    /// The steepness of the logistic curve, defaults to `DEFAULT_STEEPNESS`.
+    pub steepness: Option<f64>,
+    /// The tier labels, picked by the highest `min` threshold the normalised score reaches.
+    #[serde(default)]
+    pub tiers: Vec<Tier>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Tier {
+    pub label: String,
+    /// The lowest normalised score of the tier.
+    pub min: f64,
+}
+
+/// Parses tiers written as comma separated `label:min` pairs, e.g. `high:0.7,medium:0.3,low:0`.
+pub fn parse_tiers(tiers: &str) -> Result<Vec<Tier>, String> {
+    tiers
+        .split(',')
+        .map(|tier| {
+            let (label, min) = tier
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid tier `{}`, expected `label:min`", tier))?;
+            let min = min
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid tier threshold `{}`", min))?;
+            Ok(Tier {
+                label: label.trim().to_owned(),
+                min,
+            })
+        })
+        .collect()
+}
+
+/// The normalisation of a run, built from all of its scores.
+pub struct Normalizer {
+    method: NormalizationMethod,
+    min: f64,
+    max: f64,
+    midpoint: f64,
+    steepness: f64,
+    /// Sorted by threshold, highest first.
+    tiers: Vec<Tier>,
+    sorted: Vec<f64>,
+}
+
+impl Normalizer {
+    pub fn new(normalization: &Normalization, mut scores: Vec<f64>) -> Self {
+        scores.sort_by(f64::total_cmp);
+        let min = scores.first().copied().unwrap_or(0.0);
+        let max = scores.last().copied().unwrap_or(0.0);
+        let median = match scores.len() {
+            0 => 0.0,
+            len if len % 2 == 0 => (scores[len / 2 - 1] + scores[len / 2]) / 2.0,
+            len => scores[len / 2],
+        };
+        let mut tiers = normalization.tiers.clone();
+        tiers.sort_by(|a, b| b.min.total_cmp(&a.min));
+
+        Normalizer {
+            method: normalization.method,
+            min,
+            max,
+            midpoint: normalization.midpoint.unwrap_or(median),
+            steepness: normalization.steepness.unwrap_or(DEFAULT_STEEPNESS),
+            tiers,
+            sorted: scores,
+        }
+    }
+
+    /// Sets the normalised score, percentile rank and tier of the score.
+    pub fn apply(&self, score: &mut BiomarkerScore) {
+        let normalized = self.normalize(score.score);
+        score.normalized_score = Some(normalized);
+        score.percentile = Some(self.percentile(score.score));
+        score.tier = self
+            .tiers
+            .iter()
+            .find(|tier| normalized >= tier.min)
+            .map(|tier| tier.label.clone());
+    }
+
+    fn normalize(&self, score: f64) -> f64 {
+        match self.method {
+            // Every score is the same, so there is nothing to spread out
+            NormalizationMethod::MinMax if self.max == self.min => 0.0,
+            NormalizationMethod::MinMax => (score - self.min) / (self.max - self.min),
+            NormalizationMethod::Logistic => {
+                1.0 / (1.0 + (-self.steepness * (score - self.midpoint)).exp())
+            }
+        }
+    }
+
+    /// The percentage of the run scoring lower, counting equal scores as half.
+    fn percentile(&self, score: f64) -> f64 {
+        if self.sorted.is_empty() {
+            return 0.0;
+        }
+        let lower = self.sorted.partition_point(|&s| s < score);
+        let equal = self.sorted.partition_point(|&s| s <= score) - lower;
+        (lower as f64 + equal as f64 / 2.0) / self.sorted.len() as f64 * 100.0
+    }
+}
+
+/// Normalises every score of the score map against the whole run.
+pub fn normalize_score_map(score_map: &mut ScoreMap, normalization: &Normalization) {
+    let scores = score_map
+        .values()
+        .flat_map(|scores| scores.values().map(|score| score.score))
+        .collect();
+    let normalizer = Normalizer::new(normalization, scores);
+    for score in score_map
+        .values_mut()
+        .flat_map(|scores| scores.values_mut())
+    {
+        normalizer.apply(score);
+    }
+}
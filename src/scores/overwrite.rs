@@ -1,6 +1,9 @@
+use crate::inputs::InputFile;
 use crate::prelude::*;
+use crate::rules::report::RuleReport;
+use crate::scores::normalize::{Normalization, Normalizer};
 use crate::CHECKPOINT;
-use serde_json::json;
+use std::collections::BTreeMap;
 use std::path::Path;
 use tokio::fs;
 
@@ -10,19 +13,42 @@ enum SourceType {
     Multiple(Vec<FullBiomarker>),
 }
 
+/// Scores the biomarkers of the input files and embeds the scores in the
+/// files. The scores are also collected in `score_map` (by file key) if given,
+/// e.g. for the score statistics.
 pub async fn overwrite_source_files(
-    glob_pattern: &str,
+    inputs: &[InputFile],
     weights: &Weights,
     custom_rules: Option<CustomRules>,
+    mut rule_report: Option<&mut RuleReport>,
+    normalization: Option<&Normalization>,
+    mut score_map: Option<&mut ScoreMap>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let files = glob::glob(glob_pattern)?;
+    // Normalising needs the scores of the whole run before any file is written
+    let normalizer = match normalization {
+        Some(normalization) => {
+            let scores = collect_scores(inputs, weights, custom_rules.as_ref()).await?;
+            Some(Normalizer::new(normalization, scores))
+        }
+        None => None,
+    };
 
-    for (idx, file) in files.enumerate() {
-        let path = file?;
+    for (idx, input) in inputs.iter().enumerate() {
         if idx % CHECKPOINT == 0 {
             println!("Checkpoint reached at file index: {}", idx + 1);
         }
-        process_file(&path, weights, custom_rules.as_ref()).await?;
+        let file_scores = score_map
+            .as_deref_mut()
+            .map(|score_map| score_map.entry(input.key.clone()).or_default());
+        process_file(
+            &input.path,
+            weights,
+            custom_rules.as_ref(),
+            rule_report.as_deref_mut(),
+            normalizer.as_ref(),
+            file_scores,
+        )
+        .await?;
     }
 
     println!("All files have been processed and overwritten.");
@@ -33,21 +59,44 @@ async fn process_file(
     path: &Path,
     weights: &Weights,
     custom_rules: Option<&CustomRules>,
+    mut rule_report: Option<&mut RuleReport>,
+    normalizer: Option<&Normalizer>,
+    mut file_scores: Option<&mut BTreeMap<String, BiomarkerScore>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let contents = fs::read_to_string(path).await?;
     let mut biomarker_data = deserialize_biomarker_data(&contents)?;
 
-    match &mut biomarker_data {
-        SourceType::Single(biomarker) => {
-            let (score, score_info) = calculate_score(biomarker, weights, custom_rules);
-            biomarker.other["score"] = json!(score);
-            biomarker.other["score_info"] = json!(score_info);
+    let biomarkers = match &mut biomarker_data {
+        SourceType::Single(biomarker) => std::slice::from_mut(biomarker),
+        SourceType::Multiple(biomarkers) => biomarkers.as_mut_slice(),
+    };
+    for biomarker in biomarkers {
+        let (score, score_info) = calculate_score(biomarker, weights, custom_rules);
+        if let Some(report) = rule_report.as_deref_mut() {
+            report.record(
+                &biomarker.biomarker_id,
+                score_info.custom_rules.as_deref().unwrap_or_default(),
+            );
+        }
+        let mut score = BiomarkerScore::new(score, score_info);
+        if let Some(normalizer) = normalizer {
+            normalizer.apply(&mut score);
         }
-        SourceType::Multiple(biomarkers) => {
-            for biomarker in biomarkers {
-                let (score, score_info) = calculate_score(biomarker, weights, custom_rules);
-                biomarker.other["score"] = json!(score);
-                biomarker.other["score_info"] = json!(score_info);
+        // Drop the optional score fields of a previous run, so e.g. a stale
+        // `tier` doesn't survive a run without tiers
+        if let serde_json::Value::Object(other) = &mut biomarker.other {
+            for key in ["provenance", "normalized_score", "percentile", "tier"] {
+                other.remove(key);
+            }
+        }
+        // Embed the score fields (`score`, `score_info`, ...) in the source record
+        let fields = serde_json::to_value(&score)?;
+        if let Some(file_scores) = file_scores.as_deref_mut() {
+            file_scores.insert(biomarker.biomarker_id.clone(), score);
+        }
+        if let serde_json::Value::Object(fields) = fields {
+            for (key, value) in fields {
+                biomarker.other[key] = value;
             }
         }
     }
@@ -61,6 +110,29 @@ async fn process_file(
     Ok(())
 }
 
+/// Calculates the scores of every biomarker in the files without writing anything.
+async fn collect_scores(
+    inputs: &[InputFile],
+    weights: &Weights,
+    custom_rules: Option<&CustomRules>,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let mut scores = Vec::new();
+    for input in inputs {
+        let contents = fs::read_to_string(&input.path).await?;
+        match deserialize_biomarker_data(&contents)? {
+            SourceType::Single(biomarker) => {
+                scores.push(calculate_score(&biomarker, weights, custom_rules).0);
+            }
+            SourceType::Multiple(biomarkers) => {
+                for biomarker in &biomarkers {
+                    scores.push(calculate_score(biomarker, weights, custom_rules).0);
+                }
+            }
+        }
+    }
+    Ok(scores)
+}
+
 fn deserialize_biomarker_data(contents: &str) -> Result<SourceType, Box<dyn std::error::Error>> {
     if let Ok(biomarkers) = serde_json::from_str::<Vec<FullBiomarker>>(contents) {
         Ok(SourceType::Multiple(biomarkers))
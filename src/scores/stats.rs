@@ -0,0 +1,185 @@
+//! Stats Module
+//!
+//! Score distribution statistics over the score map, overall and broken down
+//! by source file.
+
+use crate::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The number of equal width histogram buckets between the lowest and highest score.
+pub const HISTOGRAM_BUCKETS: usize = 10;
+
+/// The percentiles reported for every distribution.
+pub const PERCENTILES: [f64; 7] = [5.0, 10.0, 25.0, 75.0, 90.0, 95.0, 99.0];
+
+/// The score distribution of the whole score map and of every source file.
+#[derive(Serialize, Debug, Clone)]
+pub struct ScoreStats {
+    pub overall: ScoreDistribution,
+    /// Keyed by score map file key. The histograms share the bucket bounds of the overall histogram.
+    pub files: BTreeMap<String, ScoreDistribution>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ScoreDistribution {
+    pub count: usize,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    pub median: Option<f64>,
+    pub percentiles: Vec<Percentile>,
+    pub histogram: Vec<HistogramBucket>,
+    /// Biomarkers hit by the generic condition penalty.
+    pub generic_condition_penalty: usize,
+    /// Biomarkers with a final score of zero.
+    pub zero_score: usize,
+    /// Biomarkers whose score was changed by clamping it to the `min_score`/`max_score` bounds.
+    pub clamped: usize,
+    /// The total frequency (`f`) of every contribution.
+    pub contribution_frequencies: BTreeMap<String, f64>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Percentile {
+    pub percentile: f64,
+    pub value: f64,
+}
+
+/// A histogram bucket, including its lower bound and excluding its upper
+/// bound (except for the last bucket).
+#[derive(Serialize, Debug, Clone)]
+pub struct HistogramBucket {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: usize,
+}
+
+/// Computes the score distribution statistics of the score map.
+pub fn score_stats(score_map: &ScoreMap) -> ScoreStats {
+    let all_scores: Vec<&BiomarkerScore> = score_map.values().flat_map(|s| s.values()).collect();
+    let bounds = histogram_bounds(&all_scores);
+
+    ScoreStats {
+        overall: distribution(&all_scores, bounds),
+        files: score_map
+            .iter()
+            .map(|(file, scores)| {
+                let scores: Vec<&BiomarkerScore> = scores.values().collect();
+                (file.clone(), distribution(&scores, bounds))
+            })
+            .collect(),
+    }
+}
+
+/// The lowest and highest score, `None` for an empty score map.
+fn histogram_bounds(scores: &[&BiomarkerScore]) -> Option<(f64, f64)> {
+    let min = scores.iter().map(|s| s.score).min_by(f64::total_cmp)?;
+    let max = scores.iter().map(|s| s.score).max_by(f64::total_cmp)?;
+    Some((min, max))
+}
+
+fn distribution(scores: &[&BiomarkerScore], bounds: Option<(f64, f64)>) -> ScoreDistribution {
+    let mut distribution = ScoreDistribution {
+        count: scores.len(),
+        ..Default::default()
+    };
+    let mut sorted: Vec<f64> = scores.iter().map(|s| s.score).collect();
+    sorted.sort_by(f64::total_cmp);
+
+    if !sorted.is_empty() {
+        distribution.min = sorted.first().copied();
+        distribution.max = sorted.last().copied();
+        distribution.mean = Some(sorted.iter().sum::<f64>() / sorted.len() as f64);
+        distribution.median = Some(percentile(&sorted, 50.0));
+        distribution.percentiles = PERCENTILES
+            .iter()
+            .map(|&p| Percentile {
+                percentile: p,
+                value: percentile(&sorted, p),
+            })
+            .collect();
+    }
+    if let Some(bounds) = bounds {
+        distribution.histogram = histogram(&sorted, bounds);
+    }
+
+    for score in scores {
+        for contribution in &score.score_info.contributions {
+            *distribution
+                .contribution_frequencies
+                .entry(contribution.c.clone())
+                .or_default() += contribution.f;
+            if contribution.c == "generic_condition_pen" && contribution.f > 0.0 {
+                distribution.generic_condition_penalty += 1;
+            }
+        }
+        if score.score_info.clamp.as_ref().is_some_and(|clamp| {
+            clamp.before_rules.is_some_and(|c| c != 0.0)
+                || clamp.after_rules.is_some_and(|c| c != 0.0)
+        }) {
+            distribution.clamped += 1;
+        }
+        if score.score == 0.0 {
+            distribution.zero_score += 1;
+        }
+    }
+
+    distribution
+}
+
+/// The percentile of the sorted values, linearly interpolated between the closest ranks.
+fn percentile(sorted: &[f64], percentile: f64) -> f64 {
+    let position = percentile / 100.0 * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64)
+}
+
+fn histogram(sorted: &[f64], (min, max): (f64, f64)) -> Vec<HistogramBucket> {
+    let buckets = if min == max { 1 } else { HISTOGRAM_BUCKETS };
+    let width = (max - min) / buckets as f64;
+    let mut histogram: Vec<HistogramBucket> = (0..buckets)
+        .map(|idx| HistogramBucket {
+            lower: min + width * idx as f64,
+            upper: if idx + 1 == buckets {
+                max
+            } else {
+                min + width * (idx + 1) as f64
+            },
+            count: 0,
+        })
+        .collect();
+    for &score in sorted {
+        let idx = if width == 0.0 {
+            0
+        } else {
+            (((score - min) / width) as usize).min(buckets - 1)
+        };
+        histogram[idx].count += 1;
+    }
+    histogram
+}
+
+impl fmt::Display for ScoreDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (Some(min), Some(max), Some(mean), Some(median)) =
+            (self.min, self.max, self.mean, self.median)
+        else {
+            return write!(f, "no scores");
+        };
+        write!(
+            f,
+            "{} scores, min {}, median {}, mean {:.2}, max {}; {} zero, {} clamped, {} with the generic condition penalty",
+            self.count,
+            min,
+            median,
+            mean,
+            max,
+            self.zero_score,
+            self.clamped,
+            self.generic_condition_penalty
+        )
+    }
+}
@@ -0,0 +1,118 @@
+//! Tables Module
+//!
+//! Reads the local lookup tables referenced from the weights, e.g. the
+//! publication year of every PMID for recency scoring or the known LOINC codes.
+
+use crate::formats::{read_file, ParseError};
+use csv::StringRecord;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Reads a PMID to publication year table. CSV and TSV files (by extension)
+/// are read as `pmid` and `year` columns with a header row, any other file as
+/// a JSON, YAML or TOML map of PMID to year.
+pub fn read_pmid_years(path: &str) -> Result<HashMap<String, i32>, ParseError> {
+    let Some(mut reader) = csv_reader(path)? else {
+        return read_file(path);
+    };
+
+    let mut years = HashMap::new();
+    for record in records(path, &mut reader) {
+        let record = record?;
+        let line = record.position().map(|p| p.line() as usize);
+        let (Some(pmid), Some(year)) = (record.get(0), record.get(1)) else {
+            return Err(table_error(
+                path,
+                line,
+                "expected `pmid` and `year` columns".to_owned(),
+            ));
+        };
+        let year = year
+            .trim()
+            .parse()
+            .map_err(|_| table_error(path, line, format!("invalid year `{}`", year)))?;
+        years.insert(pmid.trim().to_owned(), year);
+    }
+    Ok(years)
+}
+
+/// Reads a LOINC table. CSV and TSV files (by extension) are read with a
+/// header row, taking the codes from the `LOINC_NUM` column (as in the LOINC
+/// distribution) or else the first column, any other file as a JSON, YAML or
+/// TOML list of codes.
+pub fn read_loinc_codes(path: &str) -> Result<HashSet<String>, ParseError> {
+    let Some(mut reader) = csv_reader(path)? else {
+        let codes: Vec<String> = read_file(path)?;
+        return Ok(codes.into_iter().map(|c| c.trim().to_owned()).collect());
+    };
+
+    let column = reader
+        .headers()
+        .map_err(|e| table_error(path, Some(1), e.to_string()))?
+        .iter()
+        .position(|header| header.eq_ignore_ascii_case("loinc_num"))
+        .unwrap_or(0);
+    let mut codes = HashSet::new();
+    for record in records(path, &mut reader) {
+        if let Some(code) = record?.get(column) {
+            codes.insert(code.trim().to_owned());
+        }
+    }
+    Ok(codes)
+}
+
+/// Opens a CSV or TSV file (by extension) with a header row, `None` for any other file.
+fn csv_reader(path: &str) -> Result<Option<csv::Reader<File>>, ParseError> {
+    let delimiter = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("csv") => b',',
+        Some("tsv") => b'\t',
+        _ => return Ok(None),
+    };
+    csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_path(path)
+        .map(Some)
+        .map_err(|e| table_error(path, None, format!("could not read file: {}", e)))
+}
+
+/// The records of the table, with the errors located at their line.
+fn records<'a>(
+    path: &'a str,
+    reader: &'a mut csv::Reader<File>,
+) -> impl Iterator<Item = Result<StringRecord, ParseError>> + 'a {
+    reader.records().map(move |record| {
+        record.map_err(|e| {
+            let line = e.position().map(|p| p.line() as usize);
+            table_error(path, line, e.to_string())
+        })
+    })
+}
+
+fn table_error(path: &str, line: Option<usize>, message: String) -> ParseError {
+    ParseError {
+        path: path.to_owned(),
+        line,
+        column: None,
+        message,
+    }
+}
+
+/// The current calendar year (UTC).
+pub fn current_year() -> i32 {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86_400) as i64;
+    // Converts the days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    year as i32
+}